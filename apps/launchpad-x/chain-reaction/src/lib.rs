@@ -3,7 +3,7 @@ use std::error::Error;
 use std::time::Duration;
 use std::time::Instant;
 
-use midichan_core::message::{MidiMessage, MessageType};
+use midichan_core::event::MidiEvent;
 use midichan_core::device::Application;
 use launchpad_x::*;
 
@@ -72,10 +72,10 @@ pub struct ChainReaction {
     launchpad: LaunchpadX
 }
 
-fn midi_to_item(msg: &MidiMessage) -> Option<(u8, u8)> {
-    let row = msg.key / 10;
-    let col = msg.key % 10;
-    if col > 8 || row == 0 || col == 0 || msg.msg_type == MessageType::CC {
+fn note_to_item(note: u8) -> Option<(u8, u8)> {
+    let row = note / 10;
+    let col = note % 10;
+    if col > 8 || row == 0 || col == 0 {
         None
     } else {
         Some((row-1, col-1))
@@ -356,30 +356,33 @@ impl Application for ChainReaction {
         let mut tick_time = Duration::from_millis(600);
 
         loop {
-            match midi_in.recv_timeout(Duration::from_millis(50)) {
-                Ok(MidiMessage { msg_type: MessageType::CC, key: 98, velocity: vel, .. }) => {
-                    if vel > 0 {
+            let event = midi_in.recv_timeout(Duration::from_millis(50)).ok()
+                .and_then(|msg| msg.event());
+
+            match event {
+                Some(MidiEvent::ControlChange { cc: 98, value }) => {
+                    if value > 0 {
                         self.launchpad.clear()?;
                         break;
                     }
                 },
 
-                Ok(MidiMessage { msg_type: MessageType::CC, key: 97, velocity: vel, .. }) => {
-                    if vel > 0 {
+                Some(MidiEvent::ControlChange { cc: 97, value }) => {
+                    if value > 0 {
                         self.reset()?;
                     }
                 },
 
-                Ok(MidiMessage { msg_type: MessageType::CC, key: 96, velocity: vel, .. }) => {
-                    tick_time = if vel > 0 {
+                Some(MidiEvent::ControlChange { cc: 96, value }) => {
+                    tick_time = if value > 0 {
                         Duration::from_millis(50)
                     } else {
                         Duration::from_millis(600)
                     }
                 },
 
-                Ok(MidiMessage { msg_type: MessageType::CC, key: 95, velocity: vel, .. }) if matches!(self.state, ChainState::Empty) => {
-                    if vel > 0 {
+                Some(MidiEvent::ControlChange { cc: 95, value }) if matches!(self.state, ChainState::Empty) => {
+                    if value > 0 {
                         self.player_count = if self.player_count as usize == self.colors.len() - 1 {
                             2
                         } else {
@@ -389,9 +392,9 @@ impl Application for ChainReaction {
                     }
                 },
 
-                Ok(msg) if matches!(msg.msg_type, MessageType::NoteOn) && !self.has_boom => {
-                    if let Some((row, col)) = midi_to_item(&msg) {
-                        if msg.velocity > 0 {
+                Some(MidiEvent::NoteOn { note, vel }) if !self.has_boom => {
+                    if let Some((row, col)) = note_to_item(note) {
+                        if vel > 0 {
                             self.step(row, col);
                             self.launchpad.set(col, row, lpx_color!(36))?;
                             self.render_menu()?;
@@ -1,6 +1,5 @@
 use std::error::Error;
 
-use midichan_core::message::{MidiMessage, MessageType};
 use midichan_core::device::Application;
 use launchpad_x::*;
 
@@ -11,16 +10,6 @@ pub struct Select {
     text_mode: bool
 }
 
-fn midi_to_item(msg: &MidiMessage) -> usize {
-    let row = msg.key / 10;
-    let col = msg.key % 10;
-    if col > 8 || row == 0 || col == 0 || msg.msg_type == MessageType::CC {
-        255
-    } else {
-        ((row-1) * 8 + (col-1)) as usize
-    }
-}
-
 impl Select {
     pub fn new(launchpad: LaunchpadX) -> Select {
         Select {
@@ -57,7 +46,6 @@ impl Select {
 
 impl Application for Select {
     fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        let midi_in = self.launchpad.daw_input();
         self.launchpad.set_screen(LaunchpadScreen::Session)?;
 
         std::thread::sleep(std::time::Duration::from_millis(100));
@@ -65,19 +53,18 @@ impl Application for Select {
 
         loop {
 
-            match midi_in.recv()? {
-                MidiMessage { msg_type: MessageType::CC, key: 98, velocity: vel, .. } if vel > 0 => {
+            match self.launchpad.recv_event()? {
+                PadEvent::TopButton(Arrow::Capture, true) => {
                     self.launchpad.clear()?;
                     break;
                 },
-                MidiMessage { msg_type: MessageType::CC, key: 89, velocity: vel, .. } if vel > 0 => {
+                PadEvent::SideButton(7, true) => {
                     self.text_mode = !self.text_mode;
                     self.display_choices()?;
                 },
 
-                msg if msg.velocity > 0 => {
-                    
-                    if let Some((name, x)) = self.choices.get_mut(midi_to_item(&msg)) {
+                PadEvent::Press { x, y, .. } => {
+                    if let Some((name, x)) = self.choices.get_mut((y * 8 + x) as usize) {
                         if self.text_mode {
                             self.launchpad.scroll_text(&name, Color { color: 21, pulse_mode: PulseMode::Static }, 10, false)?;
                         } else {
@@ -0,0 +1,461 @@
+//! 8 track, 64 step sequencer.
+//!
+//! - The right column selects the track, pressing the selected one again mutes it.
+//! - The top row selects the page of 8 steps, the page with the playhead flashes.
+//!   Holding the last page button for a second exits.
+//! - The grid shows the steps of the page for the selected track, as velocity bars:
+//!   pressing a pad sets the step's velocity to its height, pressing the top of a bar clears it.
+//! - Holding a step and pressing a later one sets the note length to reach it.
+
+#[macro_use]
+extern crate crossbeam_channel;
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, Sender, Receiver};
+
+use midichan_core::clock::{ClockMaster, ClockFollower, ClockEvent, PPQN};
+use midichan_core::device::Application;
+use midichan_core::event::MidiEvent;
+use midichan_core::message::MidiMessage;
+use launchpad_x::*;
+
+pub const TRACKS: usize = 8;
+pub const STEPS: usize = 64;
+const STEPS_PER_PAGE: usize = 8;
+
+/// Steps are 16th notes.
+const CLOCKS_PER_STEP: u64 = PPQN / 4;
+const EXIT_HOLD: Duration = Duration::from_secs(1);
+
+/// Bright colors, the dim ones are 2 further in the palette.
+const TRACK_COLORS: [u8; TRACKS] = [5, 9, 13, 21, 37, 45, 53, 57];
+const PLAYHEAD_COLOR: u8 = 1;
+const MUTED_COLOR: u8 = 7;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Step {
+    /// 0 if the step is off.
+    pub velocity: u8,
+    /// In steps.
+    pub length: u8
+}
+
+impl Step {
+    pub fn is_on(&self) -> bool {
+        self.velocity > 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub note: u8,
+    pub channel: u8,
+    pub is_muted: bool,
+    pub steps: [Step; STEPS],
+
+    /// Clocks until the sounding note ends.
+    sounding: Option<u64>
+}
+
+impl Track {
+    fn new(note: u8, channel: u8) -> Track {
+        Track {
+            note, channel,
+            is_muted: false,
+            steps: [Step::default(); STEPS],
+            sounding: None
+        }
+    }
+}
+
+/// Bar height of the velocity, 0..=7.
+fn velocity_level(velocity: u8) -> u8 {
+    velocity.saturating_sub(1) / 16
+}
+
+fn level_velocity(level: u8) -> u8 {
+    (level + 1) * 16 - 1
+}
+
+fn note_to_pad(note: u8) -> Option<(u8, u8)> {
+    let row = note / 10;
+    let col = note % 10;
+    if col > 8 || row == 0 || col == 0 {
+        None
+    } else {
+        Some((col-1, row-1))
+    }
+}
+
+/// Top row and right column buttons, as (x, y).
+fn cc_to_button(cc: u8) -> Option<(u8, u8)> {
+    match (cc / 10, cc % 10) {
+        (9, col @ 1..=8) => Some((col - 1, 8)),
+        (row @ 1..=8, 9) => Some((8, row - 1)),
+        _ => None
+    }
+}
+
+pub struct Sequencer {
+    tracks: Vec<Track>,
+    track: usize,
+    page: usize,
+    /// Step last played, None before the first one.
+    playhead: Option<usize>,
+    /// Step held down, for setting its length.
+    held: Option<usize>,
+    exit_pressed: Option<Instant>,
+
+    bpm: f64,
+    external_clock: Option<Receiver<MidiMessage>>,
+
+    output_name: String,
+    output: Sender<MidiMessage>,
+    launchpad: LaunchpadX
+}
+
+impl Sequencer {
+    /// Notes go to `output`, starting on the GM drum channel from the kick drum up.
+    pub fn new(launchpad: LaunchpadX, output: Sender<MidiMessage>) -> Sequencer {
+        Sequencer {
+            tracks: (0..TRACKS as u8).map(|track| Track::new(36 + track, 9)).collect(),
+            track: 0,
+            page: 0,
+            playhead: None,
+            held: None,
+            exit_pressed: None,
+
+            bpm: 120.0,
+            external_clock: None,
+
+            output_name: "Sequencer".to_string(),
+            output,
+            launchpad
+        }
+    }
+
+    /// Device name of the notes sent out, for picking the port on an `OutputDevice`.
+    pub fn with_output_name(&mut self, output_name: String) -> &mut Sequencer {
+        self.output_name = output_name;
+        self
+    }
+
+    pub fn with_track(&mut self, track: usize, note: u8, channel: u8) -> &mut Sequencer {
+        if let Some(track) = self.tracks.get_mut(track) {
+            track.note = note & 0x7F;
+            track.channel = channel & 0x0F;
+        }
+        self
+    }
+
+    /// Tempo of the internal clock.
+    pub fn with_bpm(&mut self, bpm: f64) -> &mut Sequencer {
+        self.bpm = bpm;
+        self
+    }
+
+    /// Follows the clock, start and stop messages from `clock` instead of the internal clock.
+    pub fn with_external_clock(&mut self, clock: Receiver<MidiMessage>) -> &mut Sequencer {
+        self.external_clock = Some(clock);
+        self
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn tracks_mut(&mut self) -> &mut [Track] {
+        &mut self.tracks
+    }
+
+    fn send_note(&self, track: usize, velocity: u8) -> Result<(), Box<dyn Error>> {
+        let track = &self.tracks[track];
+        let event = match velocity {
+            0 => MidiEvent::NoteOff { note: track.note, vel: 0 },
+            _ => MidiEvent::NoteOn { note: track.note, vel: velocity }
+        };
+
+        self.output.send(MidiMessage::from_event(&self.output_name, track.channel, &event))?;
+        Ok(())
+    }
+
+    fn notes_off(&mut self) -> Result<(), Box<dyn Error>> {
+        for track in 0..TRACKS {
+            if self.tracks[track].sounding.take().is_some() {
+                self.send_note(track, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn clock(&mut self, position: u64) -> Result<(), Box<dyn Error>> {
+        for track in 0..TRACKS {
+            let remaining = match self.tracks[track].sounding {
+                Some(remaining) => remaining.saturating_sub(1),
+                None => continue
+            };
+
+            if remaining == 0 {
+                self.tracks[track].sounding = None;
+                self.send_note(track, 0)?;
+            } else {
+                self.tracks[track].sounding = Some(remaining);
+            }
+        }
+
+        let step = match position % CLOCKS_PER_STEP {
+            0 => (position / CLOCKS_PER_STEP) as usize % STEPS,
+            _ => return Ok(())
+        };
+        for track in 0..TRACKS {
+            let Step { velocity, length } = self.tracks[track].steps[step];
+            if velocity == 0 || self.tracks[track].is_muted {
+                continue;
+            }
+
+            if self.tracks[track].sounding.is_some() {
+                self.send_note(track, 0)?;
+            }
+            self.send_note(track, velocity)?;
+            self.tracks[track].sounding = Some(length.max(1) as u64 * CLOCKS_PER_STEP);
+        }
+
+        self.move_playhead(Some(step))
+    }
+
+    fn move_playhead(&mut self, playhead: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let old = std::mem::replace(&mut self.playhead, playhead);
+
+        for step in old.into_iter().chain(playhead) {
+            if step / STEPS_PER_PAGE == self.page {
+                self.render_column(step % STEPS_PER_PAGE)?;
+            }
+        }
+
+        if old.map(|step| step / STEPS_PER_PAGE) != playhead.map(|step| step / STEPS_PER_PAGE) {
+            self.render_pages()?;
+        }
+        Ok(())
+    }
+
+    fn render_column(&self, column: usize) -> Result<(), Box<dyn Error>> {
+        let track = &self.tracks[self.track];
+        let step_index = self.page * STEPS_PER_PAGE + column;
+        let step = track.steps[step_index];
+        let color = TRACK_COLORS[self.track];
+
+        // Steps still sounding from an earlier one in the page, shown on the bottom pad.
+        let is_tail = (0..column).any(|earlier| {
+            let earlier_index = self.page * STEPS_PER_PAGE + earlier;
+            let earlier_step = track.steps[earlier_index];
+            earlier_step.is_on() && earlier_index + earlier_step.length as usize > step_index
+        });
+
+        for row in 0..8 {
+            let pad = if step.is_on() && row < velocity_level(step.velocity) {
+                color + 2
+            } else if step.is_on() && row == velocity_level(step.velocity) {
+                color
+            } else if is_tail && row == 0 {
+                color + 2
+            } else if self.playhead == Some(step_index) {
+                PLAYHEAD_COLOR
+            } else {
+                0
+            };
+
+            self.launchpad.set(column as u8, row, lpx_color!(pad))?;
+        }
+        Ok(())
+    }
+
+    fn render_pages(&self) -> Result<(), Box<dyn Error>> {
+        let playhead_page = self.playhead.map(|step| step / STEPS_PER_PAGE);
+
+        for page in 0..(STEPS / STEPS_PER_PAGE) {
+            let color = match (page == self.page, Some(page) == playhead_page) {
+                (true, true) => lpx_color!(3, flash),
+                (true, false) => lpx_color!(3),
+                (false, true) => lpx_color!(1, flash),
+                (false, false) => lpx_color!(0)
+            };
+            self.launchpad.set(page as u8, 8, color)?;
+        }
+        Ok(())
+    }
+
+    fn render_tracks(&self) -> Result<(), Box<dyn Error>> {
+        for (index, (track, track_color)) in self.tracks.iter().zip(TRACK_COLORS.iter()).enumerate() {
+            let color = match (index == self.track, track.is_muted) {
+                (_, true) => lpx_color!(MUTED_COLOR),
+                (true, false) => lpx_color!(*track_color),
+                (false, false) => lpx_color!(track_color + 2)
+            };
+            // Track 0 is on top.
+            self.launchpad.set(8, 7 - index as u8, color)?;
+        }
+        Ok(())
+    }
+
+    fn render(&self) -> Result<(), Box<dyn Error>> {
+        for column in 0..STEPS_PER_PAGE {
+            self.render_column(column)?;
+        }
+        self.render_pages()?;
+        self.render_tracks()
+    }
+
+    fn press_pad(&mut self, column: u8, row: u8, velocity: u8) -> Result<(), Box<dyn Error>> {
+        let step_index = self.page * STEPS_PER_PAGE + column as usize;
+
+        if velocity == 0 {
+            if self.held == Some(step_index) {
+                self.held = None;
+            }
+            return Ok(());
+        }
+
+        if let Some(held) = self.held {
+            if held < step_index && self.tracks[self.track].steps[held].is_on() {
+                self.tracks[self.track].steps[held].length = (step_index - held + 1) as u8;
+                return self.render();
+            }
+        }
+
+        let step = &mut self.tracks[self.track].steps[step_index];
+        if step.is_on() && velocity_level(step.velocity) == row {
+            step.velocity = 0;
+        } else {
+            step.velocity = level_velocity(row);
+            step.length = step.length.max(1);
+        }
+        self.held = Some(step_index);
+
+        self.render()
+    }
+
+    fn press_button(&mut self, cc: u8, value: u8) -> Result<bool, Box<dyn Error>> {
+        match cc_to_button(cc) {
+            // The last page button also exits when held.
+            Some((7, 8)) if value == 0 => {
+                let held = self.exit_pressed.take().map(|time| time.elapsed());
+                return Ok(held >= Some(EXIT_HOLD));
+            },
+
+            Some((x, 8)) if x < 8 && value > 0 => {
+                if x == 7 {
+                    self.exit_pressed = Some(Instant::now());
+                }
+
+                self.page = x as usize;
+                self.render()?;
+            },
+
+            Some((8, y)) if y < 8 && value > 0 => {
+                let track = 7 - y as usize;
+                if track == self.track {
+                    self.tracks[track].is_muted = !self.tracks[track].is_muted;
+                } else {
+                    self.track = track;
+                }
+                self.render()?;
+            },
+
+            _ => ()
+        }
+
+        Ok(false)
+    }
+}
+
+impl Application for Sequencer {
+    fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let midi_in = self.launchpad.input();
+        self.launchpad.set_programmer_mode(true)?;
+
+        let mut follower = ClockFollower::new();
+        let (clock_in, master) = match self.external_clock.clone() {
+            Some(clock) => (clock, None),
+            None => {
+                let (clock_send, clock_recv) = unbounded();
+                let master = ClockMaster::new("Sequencer clock", clock_send, self.bpm);
+                master.start()?;
+                (clock_recv, Some(master))
+            }
+        };
+
+        self.playhead = None;
+        self.render()?;
+
+        loop {
+            select! {
+                recv(midi_in) -> msg => match msg?.event() {
+                    Some(MidiEvent::NoteOn { note, vel }) => {
+                        if let Some((x, y)) = note_to_pad(note) {
+                            self.press_pad(x, y, vel)?;
+                        }
+                    },
+
+                    Some(MidiEvent::ControlChange { cc, value }) => {
+                        let should_exit = self.press_button(cc, value)?;
+                        if should_exit {
+                            break;
+                        }
+                    },
+
+                    _ => ()
+                },
+
+                recv(clock_in) -> msg => match follower.feed(&msg?) {
+                    Some(ClockEvent::Tick(position)) | Some(ClockEvent::Beat(position)) => self.clock(position)?,
+                    Some(ClockEvent::Start) | Some(ClockEvent::Stop) => {
+                        self.notes_off()?;
+                        self.move_playhead(None)?;
+                    },
+                    _ => ()
+                }
+            }
+        }
+
+        if let Some(master) = master {
+            master.stop()?;
+        }
+        self.notes_off()?;
+        self.playhead = None;
+
+        self.launchpad.clear()?;
+        self.launchpad.set_programmer_mode(false)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_button_selects_and_mutes_track() {
+        let (_to_input, input) = unbounded();
+        let (output, _from_output) = unbounded();
+        let (_to_daw_input, daw_input) = unbounded();
+        let (daw_output, _from_daw_output) = unbounded();
+        let launchpad = LaunchpadX::new(input, output, daw_input, daw_output).unwrap();
+
+        let (notes, _notes_recv) = unbounded();
+        let mut sequencer = Sequencer::new(launchpad, notes);
+
+        // Bottom of the right column is the last track.
+        assert!(!sequencer.press_button(19, 127).unwrap());
+        assert_eq!(sequencer.track, 7);
+        assert!(!sequencer.tracks[7].is_muted);
+
+        sequencer.press_button(19, 127).unwrap();
+        assert!(sequencer.tracks[7].is_muted);
+
+        sequencer.press_button(89, 127).unwrap();
+        assert_eq!(sequencer.track, 0);
+    }
+}
@@ -13,6 +13,22 @@ use launchpad_x::*;
 
 const DELAY: Duration = Duration::from_millis(50);
 
+/// Passes button presses to the "draw" output.
+fn route_pressed(msg: &mut MidiMessage) -> Vec<String> {
+    match msg.velocity {
+        0 => vec!(),
+        _ => vec!("draw".to_string())
+    }
+}
+
+/// Passes button releases to the "draw" output.
+fn route_released(msg: &mut MidiMessage) -> Vec<String> {
+    match msg.velocity {
+        0 => vec!("draw".to_string()),
+        _ => vec!()
+    }
+}
+
 pub struct DisplayPressed {
     color: Color,
     launchpad: LaunchpadX
@@ -91,12 +107,12 @@ impl DrawOneColor {
 
 impl Application for DrawOneColor {
     fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        let router = Router::on_off();
+        let router = Router::with_function(route_pressed);
         router.add_input("DrawInput".to_string(), self.launchpad.input())?;
 
         let (midi_send, midi_recv) = bounded(128);
 
-        router.add_output("on".to_string(), midi_send.clone())?;
+        router.add_output("draw".to_string(), midi_send)?;
 
         let output = self.launchpad.output();
         self.launchpad.set_programmer_mode(true)?;
@@ -109,13 +125,11 @@ impl Application for DrawOneColor {
                 },
 
                 MidiMessage { msg_type: MessageType::CC, key: 97, .. } => {
-                    router.remove_output("on".to_string())?;
-                    router.add_output("off".to_string(), midi_send.clone())?;
+                    router.set_function(Box::new(route_released))?;
                 },
 
                 MidiMessage { msg_type: MessageType::CC, key: 96, .. } => {
-                    router.remove_output("off".to_string())?;
-                    router.add_output("on".to_string(), midi_send.clone())?;
+                    router.set_function(Box::new(route_pressed))?;
                 },
 
                 msg if matches!(msg.msg_type, MessageType::NoteOn | MessageType::CC) => {
@@ -186,6 +200,7 @@ impl Application for Rainbow {
 
         let midi_in = self.launchpad.input();
         self.launchpad.set_programmer_mode(true)?;
+        let mut canvas = Canvas::new();
 
         loop {
             select!{
@@ -206,7 +221,7 @@ impl Application for Rainbow {
                         }
 
                         if let Some(color) = colorloop.get(states[row][column] as usize / 2) {
-                            self.launchpad.set(7 - row as u8, column as u8, *color)?;
+                            canvas.set_color(7 - row as u8, column as u8, *color);
                         }
 
                         if column < 3 {
@@ -221,11 +236,13 @@ impl Application for Rainbow {
                             }
 
                             if let Some(color) = colorloop.get(states[row][column] as usize / 2) {
-                                self.launchpad.set(7 - row as u8, column as u8, *color)?;
+                                canvas.set_color(7 - row as u8, column as u8, *color);
                             }
                         }
                     }
 
+                    canvas.flush(&self.launchpad)?;
+
                     if ending {
                         let mut finished = true;
 
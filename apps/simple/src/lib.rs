@@ -13,6 +13,22 @@ use launchpad::{Launchpad, Color};
 
 const DELAY: Duration = Duration::from_millis(50);
 
+/// Passes button presses to the "draw" output.
+fn route_pressed(msg: &mut MidiMessage) -> Vec<String> {
+    match msg.velocity {
+        0 => vec!(),
+        _ => vec!("draw".to_string())
+    }
+}
+
+/// Passes button releases to the "draw" output.
+fn route_released(msg: &mut MidiMessage) -> Vec<String> {
+    match msg.velocity {
+        0 => vec!("draw".to_string()),
+        _ => vec!()
+    }
+}
+
 pub struct DisplayPressed {
     color: Color,
     input: Receiver<MidiMessage>,
@@ -68,12 +84,12 @@ impl DrawOneColor {
 
 impl Application for DrawOneColor {
     fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        let router = Router::on_off();
+        let router = Router::with_function(route_pressed);
         router.add_input("DrawInput".to_string(), self.input.clone())?;
 
         let (midi_send, midi_recv) = bounded(128);
 
-        router.add_output("on".to_string(), midi_send.clone())?;
+        router.add_output("draw".to_string(), midi_send)?;
 
         loop {
             match midi_recv.recv()? {
@@ -82,13 +98,11 @@ impl Application for DrawOneColor {
                 },
 
                 MidiMessage { msg_type: MessageType::CC, key: 110, .. } => {
-                    router.remove_output("on".to_string())?;
-                    router.add_output("off".to_string(), midi_send.clone())?;
+                    router.set_function(Box::new(route_released))?;
                 },
 
                 MidiMessage { msg_type: MessageType::CC, key: 109, .. } => {
-                    router.remove_output("off".to_string())?;
-                    router.add_output("on".to_string(), midi_send.clone())?;
+                    router.set_function(Box::new(route_pressed))?;
                 },
 
                 msg => {
@@ -0,0 +1,148 @@
+#[macro_use]
+extern crate crossbeam_channel;
+
+mod palette;
+mod state;
+mod terminal;
+
+pub use palette::palette_rgb;
+pub use state::{LaunchpadState, Led, Port, ScrollingText, Grid};
+
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{bounded, Sender, Receiver};
+use midichan_core::message::MidiMessage;
+
+/// Launchpad X rendered in the terminal.
+///
+/// Takes over the terminal until dropped, or until Esc is pressed.
+/// Pass its ports to `LaunchpadX::new` in place of the physical ones.
+pub struct Emulator {
+    input: Receiver<MidiMessage>,
+    output: Sender<MidiMessage>,
+    daw_input: Receiver<MidiMessage>,
+    daw_output: Sender<MidiMessage>,
+
+    shutdown: Sender<()>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl Emulator {
+    pub fn new() -> Emulator {
+        let (to_midi, input) = bounded(128);
+        let (output, from_midi) = bounded(128);
+        let (to_daw, daw_input) = bounded(128);
+        let (daw_output, from_daw) = bounded(128);
+        let (shutdown, thread_shutdown) = bounded(1);
+
+        let ports = terminal::Ports {
+            from_midi, from_daw, to_midi, to_daw,
+            shutdown: thread_shutdown
+        };
+
+        let handle = thread::spawn(move || {
+            terminal::terminal_wrapper(ports);
+        });
+
+        Emulator{input, output, daw_input, daw_output, shutdown, handle: Some(handle)}
+    }
+
+    /// MIDI port, messages coming from the device.
+    pub fn input(&self) -> Receiver<MidiMessage> {
+        self.input.clone()
+    }
+
+    /// MIDI port, messages going to the device.
+    pub fn output(&self) -> Sender<MidiMessage> {
+        self.output.clone()
+    }
+
+    pub fn daw_input(&self) -> Receiver<MidiMessage> {
+        self.daw_input.clone()
+    }
+
+    pub fn daw_output(&self) -> Sender<MidiMessage> {
+        self.daw_output.clone()
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Emulator {
+        Emulator::new()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.shutdown.send(()).ok();
+        self.handle.take().map(|x| x.join());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crossbeam_channel::unbounded;
+    use launchpad_x::{LaunchpadX, AftertouchMode, AftertouchThreshold, PadEvent, VelocityCurve};
+    use midichan_core::message::MessageType;
+
+    /// A device answering from the emulated state, sending a pad press before every answer.
+    fn device() -> (LaunchpadX, JoinHandle<LaunchpadState>) {
+        let (to_device, from_launchpad) = unbounded();
+        let (to_launchpad, from_device) = unbounded();
+        let (daw_output, _) = unbounded();
+        let (_, daw_input) = unbounded();
+
+        let handle = thread::spawn(move || {
+            let mut state = LaunchpadState::new();
+
+            for msg in from_launchpad.iter() {
+                if let Some(answer) = state.apply(Port::Midi, &msg) {
+                    let (_, press) = state.press(0, 0, 100);
+                    to_launchpad.send(press).unwrap();
+                    to_launchpad.send(answer).unwrap();
+                }
+            }
+            state
+        });
+
+        (LaunchpadX::new(from_device, to_device, daw_input, daw_output).unwrap(), handle)
+    }
+
+    #[test]
+    fn aftertouch_readback() {
+        let (mut launchpad, handle) = device();
+        launchpad.set_programmer_mode(true).unwrap();
+
+        assert_eq!(launchpad.aftertouch().unwrap(), (AftertouchMode::Polyphonic, AftertouchThreshold::Low));
+        launchpad.set_aftertouch(AftertouchMode::Channel, AftertouchThreshold::High).unwrap();
+        assert_eq!(launchpad.aftertouch().unwrap(), (AftertouchMode::Channel, AftertouchThreshold::High));
+
+        // The presses that came in before the answers are kept.
+        assert_eq!(launchpad.recv_event_timeout(Duration::from_millis(100)), Ok(PadEvent::Press{x: 0, y: 0, velocity: 100}));
+        let held = launchpad.take_held_input();
+        assert_eq!(held.len(), 1);
+        assert_eq!((held[0].msg_type.clone(), held[0].key), (MessageType::NoteOn, 11));
+
+        drop(launchpad);
+        let state = handle.join().unwrap();
+        assert_eq!(state.aftertouch, (AftertouchMode::Channel as u8, AftertouchThreshold::High as u8));
+    }
+
+    #[test]
+    fn velocity_curve_readback() {
+        let (launchpad, handle) = device();
+
+        assert_eq!(launchpad.velocity_curve().unwrap(), (VelocityCurve::Medium, 127));
+        launchpad.set_velocity_curve(VelocityCurve::High, 0).unwrap();
+        assert_eq!(launchpad.velocity_curve().unwrap(), (VelocityCurve::High, 1));
+        launchpad.set_fixed_velocity(90).unwrap();
+        assert_eq!(launchpad.velocity_curve().unwrap(), (VelocityCurve::Fixed, 90));
+
+        drop(launchpad);
+        let state = handle.join().unwrap();
+        assert_eq!(state.velocity_curve, (VelocityCurve::Fixed as u8, 90));
+    }
+}
@@ -0,0 +1,58 @@
+/// Approximate RGB value of a palette entry, 8 bits per channel.
+///
+/// 0-3 are black to white, 4-63 are 15 hues in 4 shades each, the rest are spread over the hue wheel.
+pub fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    match index & 0x7F {
+        0 => (0, 0, 0),
+        1 => (30, 30, 30),
+        2 => (127, 127, 127),
+        3 => (255, 255, 255),
+
+        index @ 4..=63 => {
+            let hue = ((index - 4) / 4) as u16 * 24;
+            match (index - 4) % 4 {
+                0 => mix(hsv(hue), (255, 255, 255), 2),
+                1 => hsv(hue),
+                2 => scale(hsv(hue), 2),
+                _ => scale(hsv(hue), 4)
+            }
+        },
+
+        index => {
+            let hue = ((index - 64) as u16 * 37) % 360;
+            match index % 3 {
+                0 => hsv(hue),
+                1 => scale(hsv(hue), 2),
+                _ => mix(hsv(hue), (255, 255, 255), 2)
+            }
+        }
+    }
+}
+
+/// Full saturation and value.
+fn hsv(hue: u16) -> (u8, u8, u8) {
+    let sector = hue / 60;
+    let rising = ((hue % 60) * 255 / 60) as u8;
+    let falling = 255 - rising;
+
+    match sector {
+        0 => (255, rising, 0),
+        1 => (falling, 255, 0),
+        2 => (0, 255, rising),
+        3 => (0, falling, 255),
+        4 => (rising, 0, 255),
+        _ => (255, 0, falling)
+    }
+}
+
+fn scale((red, green, blue): (u8, u8, u8), divisor: u8) -> (u8, u8, u8) {
+    (red / divisor, green / divisor, blue / divisor)
+}
+
+fn mix((red, green, blue): (u8, u8, u8), (other_red, other_green, other_blue): (u8, u8, u8), divisor: u16) -> (u8, u8, u8) {
+    (
+        ((red as u16 * (divisor - 1) + other_red as u16) / divisor) as u8,
+        ((green as u16 * (divisor - 1) + other_green as u16) / divisor) as u8,
+        ((blue as u16 * (divisor - 1) + other_blue as u16) / divisor) as u8
+    )
+}
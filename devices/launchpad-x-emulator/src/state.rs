@@ -0,0 +1,404 @@
+use midichan_core::message::{MidiMessage, MessageType};
+use launchpad_x::{BYTE_HEADER, LaunchpadScreen, AftertouchMode, AftertouchThreshold, VelocityCurve};
+
+use crate::palette::palette_rgb;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    Midi,
+    Daw
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Led {
+    Off,
+    Static(u8),
+    /// Flashes between the first and second color.
+    Flash(u8, u8),
+    Pulse(u8),
+    /// 7-bit components.
+    Rgb(u8, u8, u8)
+}
+
+impl Led {
+    /// Color shown at `millis` into the animation, 8 bits per channel.
+    pub fn rgb(&self, millis: u64) -> (u8, u8, u8) {
+        match *self {
+            Led::Off => (0, 0, 0),
+            Led::Static(color) => palette_rgb(color),
+            Led::Flash(first, second) => {
+                // Flashing and pulsing follows a 120 BPM clock, like the device without a MIDI clock.
+                if millis % 500 < 250 { palette_rgb(second) } else { palette_rgb(first) }
+            },
+            Led::Pulse(color) => {
+                let (red, green, blue) = palette_rgb(color);
+                let phase = (millis % 1000) as u32;
+                let level = 64 + if phase < 500 { phase } else { 1000 - phase } * 191 / 500;
+
+                (
+                    (red as u32 * level / 255) as u8,
+                    (green as u32 * level / 255) as u8,
+                    (blue as u32 * level / 255) as u8
+                )
+            },
+            Led::Rgb(red, green, blue) => (red << 1, green << 1, blue << 1)
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScrollingText {
+    pub text: String,
+    pub color: Led,
+    pub speed: u8,
+    pub is_loop: bool
+}
+
+/// LEDs are indexed `[y][x]`, with the same coordinates as `LaunchpadX::set`:
+/// the top row is `y == 8`, the right column is `x == 8`.
+pub type Grid = [[Led; 9]; 9];
+
+/// Device state, driven by the messages `LaunchpadX` sends out.
+#[derive(Clone, Debug)]
+pub struct LaunchpadState {
+    pub midi_name: String,
+    pub daw_name: String,
+
+    /// Lit through the MIDI port, shown in programmer mode.
+    pub programmer: Grid,
+    /// Lit through the DAW port, shown otherwise.
+    pub session: Grid,
+
+    pub is_daw_mode: bool,
+    pub is_programmer_mode: bool,
+    pub layout: u8,
+    pub is_sleeping: bool,
+    pub text: Option<ScrollingText>,
+    /// Mode and threshold, as sent.
+    pub aftertouch: (u8, u8),
+    /// Curve and fixed velocity, as sent.
+    pub velocity_curve: (u8, u8)
+}
+
+fn led_coords(index: u8) -> Option<(usize, usize)> {
+    let (row, col) = (index / 10, index % 10);
+
+    if (1..=9).contains(&row) && (1..=9).contains(&col) {
+        Some((col as usize - 1, row as usize - 1))
+    } else {
+        None
+    }
+}
+
+impl LaunchpadState {
+    pub fn new() -> LaunchpadState {
+        LaunchpadState {
+            midi_name: "Launchpad MIDI".to_string(),
+            daw_name: "Launchpad DAW".to_string(),
+
+            programmer: [[Led::Off; 9]; 9],
+            session: [[Led::Off; 9]; 9],
+
+            is_daw_mode: false,
+            is_programmer_mode: false,
+            layout: LaunchpadScreen::Session as u8,
+            is_sleeping: false,
+            text: None,
+            aftertouch: (AftertouchMode::Polyphonic as u8, AftertouchThreshold::Low as u8),
+            velocity_curve: (VelocityCurve::Medium as u8, 127)
+        }
+    }
+
+    /// The grid currently visible on the device.
+    pub fn visible(&self) -> &Grid {
+        if self.is_programmer_mode {
+            &self.programmer
+        } else {
+            &self.session
+        }
+    }
+
+    /// Returns the answer to queries, to be sent back on the same port.
+    pub fn apply(&mut self, port: Port, msg: &MidiMessage) -> Option<MidiMessage> {
+        match msg.msg_type {
+            MessageType::NoteOn | MessageType::CC => {
+                // Fader and drum rack channels are not emulated.
+                if msg.channel > 2 {
+                    return None;
+                }
+
+                let grid = match port {
+                    Port::Midi => &mut self.programmer,
+                    Port::Daw => &mut self.session
+                };
+
+                if let Some((x, y)) = led_coords(msg.key) {
+                    let led = &mut grid[y][x];
+                    *led = match (msg.channel, msg.velocity) {
+                        (0, 0) => Led::Off,
+                        (0, color) => Led::Static(color),
+                        (1, color) => match *led {
+                            Led::Static(base) | Led::Flash(base, _) => Led::Flash(base, color),
+                            _ => Led::Flash(0, color)
+                        },
+                        (_, color) => Led::Pulse(color)
+                    };
+                }
+            },
+
+            MessageType::SysEx => {
+                if let Some(sysex) = msg.sysex.as_ref() {
+                    if sysex.len() > BYTE_HEADER.len() && sysex.starts_with(&BYTE_HEADER) {
+                        let end = sysex.len() - (sysex.last() == Some(&0xF7)) as usize;
+                        let body = &sysex[BYTE_HEADER.len()..end];
+                        self.apply_sysex(port, body);
+
+                        return self.answer(body).map(|answer| {
+                            let mut reply = MidiMessage::new(&msg.device);
+                            reply.with_msg_type(MessageType::SysEx);
+                            reply.sysex = Some(BYTE_HEADER.iter().chain(answer.iter()).chain(&[0xF7]).copied().collect());
+                            reply
+                        });
+                    }
+                }
+            },
+
+            _ => ()
+        }
+
+        None
+    }
+
+    /// SysEx body of the answer, for the queries the device answers.
+    fn answer(&self, body: &[u8]) -> Option<Vec<u8>> {
+        match body {
+            [0x04] => Some(vec![0x04, self.velocity_curve.0, self.velocity_curve.1]),
+            [0x0B] => Some(vec![0x0B, self.aftertouch.0, self.aftertouch.1]),
+            _ => None
+        }
+    }
+
+    fn apply_sysex(&mut self, port: Port, body: &[u8]) {
+        match body {
+            [0x00, layout, ..] => {
+                self.layout = *layout;
+                self.is_programmer_mode = *layout == LaunchpadScreen::Programmer as u8;
+            },
+
+            [0x03, specs @ ..] => self.apply_led_specs(port, specs),
+
+            [0x04, curve, fixed_velocity, ..] => self.velocity_curve = (*curve, *fixed_velocity),
+
+            [0x07] => self.text = None,
+            [0x07, is_loop, speed, 0, color, text @ ..] => self.text = Some(ScrollingText {
+                text: String::from_utf8_lossy(text).into_owned(),
+                color: Led::Static(*color),
+                speed: *speed,
+                is_loop: *is_loop != 0
+            }),
+            [0x07, is_loop, speed, 1, red, green, blue, text @ ..] => self.text = Some(ScrollingText {
+                text: String::from_utf8_lossy(text).into_owned(),
+                color: Led::Rgb(*red, *green, *blue),
+                speed: *speed,
+                is_loop: *is_loop != 0
+            }),
+
+            [0x09, sleep, ..] => self.is_sleeping = *sleep != 0,
+
+            [0x0E, mode, ..] => {
+                let is_programmer_mode = *mode != 0;
+                if is_programmer_mode && !self.is_programmer_mode {
+                    self.programmer = [[Led::Off; 9]; 9];
+                }
+
+                self.is_programmer_mode = is_programmer_mode;
+                self.layout = if is_programmer_mode { LaunchpadScreen::Programmer as u8 } else { LaunchpadScreen::Session as u8 };
+            },
+
+            [0x10, daw_mode, ..] => {
+                self.is_daw_mode = *daw_mode != 0;
+                if !self.is_daw_mode {
+                    self.session = [[Led::Off; 9]; 9];
+                }
+            },
+
+            [0x0B, mode, threshold, ..] => self.aftertouch = (*mode, *threshold),
+
+            [0x12, clear_session, _clear_drum_rack, clear_cc, ..] => {
+                for (y, row) in self.session.iter_mut().enumerate() {
+                    for (x, led) in row.iter_mut().enumerate() {
+                        let is_cc = x == 8 || y == 8;
+                        if (is_cc && *clear_cc != 0) || (!is_cc && *clear_session != 0) {
+                            *led = Led::Off;
+                        }
+                    }
+                }
+            },
+
+            _ => ()
+        }
+    }
+
+    fn apply_led_specs(&mut self, port: Port, mut specs: &[u8]) {
+        let grid = match port {
+            Port::Midi => &mut self.programmer,
+            Port::Daw => &mut self.session
+        };
+
+        loop {
+            let (led, len) = match specs {
+                [0, _, color, ..] => (Led::Static(*color), 3),
+                [1, _, first, second, ..] => (Led::Flash(*first, *second), 4),
+                [2, _, color, ..] => (Led::Pulse(*color), 3),
+                [3, _, red, green, blue, ..] => (Led::Rgb(*red, *green, *blue), 5),
+                _ => return
+            };
+
+            if let Some((x, y)) = led_coords(specs[1]) {
+                grid[y][x] = led;
+            }
+
+            specs = &specs[len..];
+        }
+    }
+
+    /// Port a button press is sent out on, depending on the active layout.
+    pub fn port_for(&self, x: u8, y: u8) -> Port {
+        let is_cc = x == 8 || y == 8;
+
+        if self.is_programmer_mode {
+            Port::Midi
+        } else if self.is_daw_mode && (is_cc || self.layout == LaunchpadScreen::Session as u8) {
+            Port::Daw
+        } else {
+            Port::Midi
+        }
+    }
+
+    /// Message the device sends when a button is pressed, or released with zero velocity.
+    pub fn press(&self, x: u8, y: u8, velocity: u8) -> (Port, MidiMessage) {
+        let port = self.port_for(x, y);
+
+        let mut msg = MidiMessage::new(match port {
+            Port::Midi => &self.midi_name,
+            Port::Daw => &self.daw_name
+        });
+        msg.with_msg_type(if x == 8 || y == 8 { MessageType::CC } else { MessageType::NoteOn })
+            .with_key((y + 1) * 10 + x + 1)
+            .with_velocity(velocity & 0x7F);
+
+        (port, msg)
+    }
+}
+
+impl Default for LaunchpadState {
+    fn default() -> LaunchpadState {
+        LaunchpadState::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led(channel: u8, msg_type: MessageType, key: u8, velocity: u8) -> MidiMessage {
+        let mut msg = MidiMessage::new("Test");
+        msg.with_msg_type(msg_type).with_channel(channel).with_key(key).with_velocity(velocity);
+        msg
+    }
+
+    fn sysex(body: &[u8]) -> MidiMessage {
+        let mut msg = MidiMessage::new("Test");
+        msg.with_msg_type(MessageType::SysEx);
+        msg.sysex = Some(BYTE_HEADER.iter().chain(body).chain(&[0xF7]).copied().collect());
+        msg
+    }
+
+    #[test]
+    fn led_channels() {
+        let mut state = LaunchpadState::new();
+
+        state.apply(Port::Midi, &led(0, MessageType::NoteOn, 11, 5));
+        state.apply(Port::Midi, &led(1, MessageType::NoteOn, 11, 9));
+        state.apply(Port::Midi, &led(2, MessageType::NoteOn, 12, 7));
+        state.apply(Port::Midi, &led(1, MessageType::CC, 99, 3));
+        state.apply(Port::Daw, &led(0, MessageType::CC, 19, 21));
+        // Unemulated channel.
+        state.apply(Port::Midi, &led(3, MessageType::NoteOn, 13, 1));
+
+        assert_eq!(state.programmer[0][0], Led::Flash(5, 9));
+        assert_eq!(state.programmer[0][1], Led::Pulse(7));
+        assert_eq!(state.programmer[0][2], Led::Off);
+        assert_eq!(state.programmer[8][8], Led::Flash(0, 3));
+        assert_eq!(state.session[0][8], Led::Static(21));
+
+        state.apply(Port::Midi, &led(0, MessageType::NoteOn, 11, 0));
+        assert_eq!(state.programmer[0][0], Led::Off);
+    }
+
+    #[test]
+    fn led_specs() {
+        let mut state = LaunchpadState::new();
+
+        state.apply(Port::Midi, &sysex(&[0x03,
+            0, 11, 5,
+            1, 12, 6, 7,
+            2, 13, 8,
+            3, 99, 127, 64, 0,
+            // Out of the grid, skipped.
+            0, 10, 1
+        ]));
+
+        assert_eq!(state.programmer[0][..3], [Led::Static(5), Led::Flash(6, 7), Led::Pulse(8)]);
+        assert_eq!(state.programmer[8][8], Led::Rgb(127, 64, 0));
+        assert_eq!(state.session, [[Led::Off; 9]; 9]);
+        assert_eq!(state.programmer[8][8].rgb(0), (254, 128, 0));
+    }
+
+    #[test]
+    fn modes() {
+        let mut state = LaunchpadState::new();
+        state.apply(Port::Midi, &led(0, MessageType::NoteOn, 11, 5));
+
+        state.apply(Port::Midi, &sysex(&[0x10, 0x01]));
+        assert!(state.is_daw_mode);
+        assert_eq!(state.port_for(0, 0), Port::Daw);
+        assert_eq!(state.port_for(8, 0), Port::Daw);
+
+        state.apply(Port::Midi, &sysex(&[0x00, LaunchpadScreen::Notes as u8]));
+        assert_eq!(state.layout, LaunchpadScreen::Notes as u8);
+        assert_eq!(state.port_for(0, 0), Port::Midi);
+        assert_eq!(state.port_for(8, 0), Port::Daw);
+
+        // Entering programmer mode clears it.
+        state.apply(Port::Midi, &sysex(&[0x0E, 0x01]));
+        assert!(state.is_programmer_mode);
+        assert_eq!(state.layout, LaunchpadScreen::Programmer as u8);
+        assert_eq!(state.programmer[0][0], Led::Off);
+        assert_eq!(state.port_for(8, 0), Port::Midi);
+        assert_eq!(state.visible(), &state.programmer);
+
+        state.apply(Port::Midi, &sysex(&[0x0E, 0x00]));
+        assert!(!state.is_programmer_mode);
+        assert_eq!(state.layout, LaunchpadScreen::Session as u8);
+
+        state.apply(Port::Midi, &sysex(&[0x00, LaunchpadScreen::Programmer as u8]));
+        assert!(state.is_programmer_mode);
+    }
+
+    #[test]
+    fn answers() {
+        let mut state = LaunchpadState::new();
+
+        assert!(state.apply(Port::Midi, &sysex(&[0x04, 0x02, 0x40])).is_none());
+        assert!(state.apply(Port::Midi, &sysex(&[0x0B, 0x01, 0x02])).is_none());
+
+        let reply = state.apply(Port::Midi, &sysex(&[0x04])).unwrap();
+        assert_eq!(reply.sysex, sysex(&[0x04, 0x02, 0x40]).sysex);
+        let reply = state.apply(Port::Daw, &sysex(&[0x0B])).unwrap();
+        assert_eq!(reply.sysex, sysex(&[0x0B, 0x01, 0x02]).sysex);
+
+        assert!(state.apply(Port::Midi, &sysex(&[0x09])).is_none());
+    }
+}
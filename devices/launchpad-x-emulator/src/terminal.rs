@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{tick, Sender, Receiver};
+use crossterm::{queue, execute};
+use crossterm::cursor::{Hide, Show, MoveTo};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind,
+    EnableMouseCapture, DisableMouseCapture};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{enable_raw_mode, disable_raw_mode, Clear, ClearType,
+    EnterAlternateScreen, LeaveAlternateScreen};
+
+use midichan_core::message::MidiMessage;
+use launchpad_x::LaunchpadScreen;
+
+use crate::state::{LaunchpadState, Port};
+
+const FRAME: Duration = Duration::from_millis(33);
+
+const LEFT: u16 = 2;
+const TOP: u16 = 1;
+const CELL_WIDTH: u16 = 5;
+const CELL_HEIGHT: u16 = 2;
+
+const HELP: &str = "Mouse: pads  Arrows/s/n/c/m: top row  1-8: side buttons  Esc: quit";
+
+pub struct Ports {
+    pub from_midi: Receiver<MidiMessage>,
+    pub from_daw: Receiver<MidiMessage>,
+    pub to_midi: Sender<MidiMessage>,
+    pub to_daw: Sender<MidiMessage>,
+    pub shutdown: Receiver<()>
+}
+
+pub fn terminal_wrapper(ports: Ports) {
+    let result = enable_raw_mode()
+        .and_then(|_| execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture, Hide))
+        .map_err(|err| err.into())
+        .and_then(|_| terminal_thread(&ports));
+
+    execute!(io::stdout(), Show, DisableMouseCapture, LeaveAlternateScreen).ok();
+    disable_raw_mode().ok();
+
+    if let Err(err) = result {
+        panic!("emulator died: {}", err);
+    }
+}
+
+fn terminal_thread(ports: &Ports) -> Result<(), Box<dyn Error>> {
+    let mut state = LaunchpadState::new();
+    let mut held: Option<(u8, u8)> = None;
+
+    let start = Instant::now();
+    let frame = tick(FRAME);
+
+    let mut stdout = io::stdout();
+    queue!(stdout, Clear(ClearType::All))?;
+
+    loop {
+        select!{
+            recv(ports.from_midi) -> msg => if let Some(reply) = state.apply(Port::Midi, &msg?) {
+                ports.to_midi.try_send(reply).ok();
+            },
+            recv(ports.from_daw) -> msg => if let Some(reply) = state.apply(Port::Daw, &msg?) {
+                ports.to_daw.try_send(reply).ok();
+            },
+            recv(ports.shutdown) -> _ => return Ok(()),
+
+            recv(frame) -> _ => {
+                while event::poll(Duration::from_millis(0))? {
+                    let pressed = match event::read()? {
+                        Event::Key(key) if key.kind != KeyEventKind::Release => {
+                            let button = match key.code {
+                                KeyCode::Esc => return Ok(()),
+                                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+
+                                KeyCode::Up => Some((0, 8)),
+                                KeyCode::Down => Some((1, 8)),
+                                KeyCode::Left => Some((2, 8)),
+                                KeyCode::Right => Some((3, 8)),
+                                KeyCode::Char('s') => Some((4, 8)),
+                                KeyCode::Char('n') => Some((5, 8)),
+                                KeyCode::Char('c') => Some((6, 8)),
+                                KeyCode::Char('m') => Some((7, 8)),
+                                KeyCode::Char(num @ '1'..='8') => Some((8, b'8' - num as u8)),
+                                _ => None
+                            };
+
+                            // Terminals don't report key releases, so keys are tapped.
+                            if let Some((x, y)) = button {
+                                send(ports, &state, x, y, 127);
+                                send(ports, &state, x, y, 0);
+                            }
+                            None
+                        },
+
+                        Event::Mouse(mouse) => match mouse.kind {
+                            MouseEventKind::Down(MouseButton::Left) => cell_at(mouse.column, mouse.row),
+                            MouseEventKind::Up(MouseButton::Left) => {
+                                if let Some((x, y)) = held.take() {
+                                    send(ports, &state, x, y, 0);
+                                }
+                                None
+                            },
+                            _ => None
+                        },
+
+                        _ => None
+                    };
+
+                    if let Some((x, y)) = pressed {
+                        if let Some((old_x, old_y)) = held.replace((x, y)) {
+                            send(ports, &state, old_x, old_y, 0);
+                        }
+                        send(ports, &state, x, y, 127);
+                    }
+                }
+
+                render(&mut stdout, &state, start.elapsed().as_millis() as u64)?;
+            }
+        }
+    }
+}
+
+/// Input is dropped if the application isn't reading it.
+fn send(ports: &Ports, state: &LaunchpadState, x: u8, y: u8, velocity: u8) {
+    let (port, msg) = state.press(x, y, velocity);
+
+    match port {
+        Port::Midi => ports.to_midi.try_send(msg).ok(),
+        Port::Daw => ports.to_daw.try_send(msg).ok()
+    };
+}
+
+fn cell_at(column: u16, row: u16) -> Option<(u8, u8)> {
+    if column < LEFT || row < TOP {
+        return None;
+    }
+
+    let (x, row) = ((column - LEFT) / CELL_WIDTH, (row - TOP) / CELL_HEIGHT);
+    if (column - LEFT) % CELL_WIDTH == CELL_WIDTH - 1 || x > 8 || row > 8 {
+        None
+    } else {
+        Some((x as u8, 8 - row as u8))
+    }
+}
+
+fn render<W: Write>(out: &mut W, state: &LaunchpadState, millis: u64) -> Result<(), Box<dyn Error>> {
+    let grid = state.visible();
+
+    for (y, grid_row) in grid.iter().enumerate() {
+        for (x, led) in grid_row.iter().enumerate() {
+            let (red, green, blue) = if state.is_sleeping { (0, 0, 0) } else { led.rgb(millis) };
+            let (column, row) = (LEFT + x as u16 * CELL_WIDTH, TOP + (8 - y as u16) * CELL_HEIGHT);
+
+            for line in 0..CELL_HEIGHT {
+                queue!(out,
+                    MoveTo(column, row + line),
+                    SetBackgroundColor(Color::Rgb { r: red, g: green, b: blue }),
+                    Print("    "),
+                    ResetColor)?;
+            }
+        }
+    }
+
+    let layout = match state.layout {
+        x if x == LaunchpadScreen::Session as u8 => "Session",
+        x if x == LaunchpadScreen::Notes as u8 => "Notes",
+        x if x == LaunchpadScreen::Faders as u8 => "Faders",
+        x if x == LaunchpadScreen::Programmer as u8 => "Programmer",
+        _ => "Custom"
+    };
+    let status = format!("Layout: {}{}{}", layout,
+        if state.is_daw_mode { "  DAW mode" } else { "" },
+        if state.is_sleeping { "  Sleeping" } else { "" });
+
+    let status_row = TOP + 9 * CELL_HEIGHT + 1;
+    queue!(out,
+        MoveTo(LEFT, status_row), Clear(ClearType::CurrentLine), Print(status),
+        MoveTo(LEFT, status_row + 1), Clear(ClearType::CurrentLine))?;
+
+    if let Some(text) = state.text.as_ref() {
+        let (red, green, blue) = text.color.rgb(millis);
+        queue!(out,
+            Print("Text: "),
+            SetForegroundColor(Color::Rgb { r: red, g: green, b: blue }),
+            Print(&text.text),
+            ResetColor)?;
+    }
+
+    queue!(out, MoveTo(LEFT, status_row + 3), Print(HELP))?;
+    out.flush()?;
+
+    Ok(())
+}
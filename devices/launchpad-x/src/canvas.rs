@@ -0,0 +1,108 @@
+use std::error::Error;
+
+use crate::{LaunchpadX, Color, ColorSpec, LargeColor, PulseMode};
+
+const OFF: Cell = Cell::Palette(Color{color: 0, pulse_mode: PulseMode::Static});
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cell {
+    Palette(Color),
+    Rgb(LargeColor)
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        OFF
+    }
+}
+
+/// Frame buffer for the 9x9 LEDs, with the same coordinates as `LaunchpadX::set`.
+///
+/// Draw into it, then `flush` to send only the cells that changed since the last flush.
+/// Programmer mode only, starts out matching the cleared device.
+#[derive(Clone, Debug)]
+pub struct Canvas {
+    frame: [[Cell; 9]; 9],
+    /// None if unknown.
+    shown: [[Option<Cell>; 9]; 9]
+}
+
+impl Canvas {
+    pub fn new() -> Canvas {
+        Canvas {
+            frame: [[OFF; 9]; 9],
+            shown: [[Some(OFF); 9]; 9]
+        }
+    }
+
+    pub fn get(&self, x: u8, y: u8) -> Option<Cell> {
+        self.frame.get(y as usize)?.get(x as usize).copied()
+    }
+
+    /// Out of bounds cells are ignored.
+    pub fn set(&mut self, x: u8, y: u8, cell: Cell) -> &mut Canvas {
+        if let Some(target) = self.frame.get_mut(y as usize).and_then(|row| row.get_mut(x as usize)) {
+            *target = cell;
+        }
+        self
+    }
+
+    pub fn set_color(&mut self, x: u8, y: u8, color: Color) -> &mut Canvas {
+        self.set(x, y, Cell::Palette(color))
+    }
+
+    pub fn set_large(&mut self, x: u8, y: u8, color: LargeColor) -> &mut Canvas {
+        self.set(x, y, Cell::Rgb(color))
+    }
+
+    pub fn fill(&mut self, cell: Cell) -> &mut Canvas {
+        self.frame = [[cell; 9]; 9];
+        self
+    }
+
+    /// Turns every cell off.
+    pub fn clear(&mut self) -> &mut Canvas {
+        self.fill(OFF)
+    }
+
+    /// Sends every cell on the next flush, e.g. after the device got cleared.
+    pub fn invalidate(&mut self) {
+        self.shown = [[None; 9]; 9];
+    }
+
+    /// Palette cells are sent one by one, RGB cells together in one SysEx.
+    pub fn flush(&mut self, launchpad: &LaunchpadX) -> Result<(), Box<dyn Error>> {
+        let mut rgb_cells = Vec::new();
+
+        for y in 0..9 {
+            for x in 0..9 {
+                let cell = self.frame[y][x];
+                if self.shown[y][x] == Some(cell) {
+                    continue;
+                }
+
+                match cell {
+                    Cell::Palette(color) => launchpad.set(x as u8, y as u8, color)?,
+                    Cell::Rgb(color) => rgb_cells.push(((x as u8, y as u8), ColorSpec::Rgb(color)))
+                }
+            }
+        }
+
+        if !rgb_cells.is_empty() {
+            launchpad.set_many(&rgb_cells)?;
+        }
+
+        for (shown, frame) in self.shown.iter_mut().zip(self.frame.iter()) {
+            for (shown, cell) in shown.iter_mut().zip(frame.iter()) {
+                *shown = Some(*cell);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Canvas {
+    fn default() -> Canvas {
+        Canvas::new()
+    }
+}
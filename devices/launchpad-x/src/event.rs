@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Select, RecvError, RecvTimeoutError};
+use midichan_core::message::{MidiMessage, MessageType};
+
+use crate::{LaunchpadX, LaunchpadScreen};
+
+/// CC of the leftmost top row button.
+const TOP_CC: u8 = 91;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    Midi,
+    Daw
+}
+
+/// Top row buttons, from left to right.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Arrow {
+    Up = 0,
+    Down = 1,
+    Left = 2,
+    Right = 3,
+    Session = 4,
+    Note = 5,
+    Custom = 6,
+    Capture = 7
+}
+
+impl Arrow {
+    pub fn from_x(x: u8) -> Option<Arrow> {
+        match x {
+            0 => Some(Arrow::Up),
+            1 => Some(Arrow::Down),
+            2 => Some(Arrow::Left),
+            3 => Some(Arrow::Right),
+            4 => Some(Arrow::Session),
+            5 => Some(Arrow::Note),
+            6 => Some(Arrow::Custom),
+            7 => Some(Arrow::Capture),
+            _ => None
+        }
+    }
+
+    /// Column, as taken by `LaunchpadX::set` with `y == 8`.
+    pub fn x(self) -> u8 {
+        self as u8
+    }
+
+    pub fn cc(self) -> u8 {
+        TOP_CC + self as u8
+    }
+}
+
+/// Input from the Launchpad, with the same coordinates as `LaunchpadX::set`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PadEvent {
+    Press{x: u8, y: u8, velocity: u8},
+    Release{x: u8, y: u8},
+    /// Polyphonic aftertouch.
+    Aftertouch{x: u8, y: u8, pressure: u8},
+    /// Channel aftertouch, the highest pressure of all pads.
+    Pressure(u8),
+    /// True when pressed.
+    TopButton(Arrow, bool),
+    /// Right column, 0 is the bottom. True when pressed.
+    SideButton(u8, bool)
+}
+
+/// Grid pad at the LED index.
+fn pad(index: u8) -> Option<(u8, u8)> {
+    let (row, col) = (index / 10, index % 10);
+
+    if (1..=8).contains(&row) && (1..=8).contains(&col) {
+        Some((col - 1, row - 1))
+    } else {
+        None
+    }
+}
+
+impl LaunchpadX {
+    /// Whether grid presses come in on the port, with the current layout.
+    ///
+    /// Other layouts send notes of their own on the MIDI port, which are not decoded.
+    fn has_pads(&self, port: Port) -> bool {
+        match port {
+            Port::Midi => self.is_programmer_mode,
+            Port::Daw => !self.is_programmer_mode && matches!(self.daw_mode, LaunchpadScreen::Session)
+        }
+    }
+
+    /// None for messages that aren't button presses on the active layout.
+    pub fn decode(&self, port: Port, msg: &MidiMessage) -> Option<PadEvent> {
+        // Top and side buttons go to the DAW port unless in programmer mode.
+        let has_buttons = (port == Port::Midi) == self.is_programmer_mode;
+
+        match msg.msg_type {
+            MessageType::NoteOn | MessageType::NoteOff if self.has_pads(port) => {
+                let (x, y) = pad(msg.key)?;
+
+                match (&msg.msg_type, msg.velocity) {
+                    (MessageType::NoteOff, _) | (_, 0) => Some(PadEvent::Release{x, y}),
+                    (_, velocity) => Some(PadEvent::Press{x, y, velocity})
+                }
+            },
+
+            MessageType::NoteVelocity if self.has_pads(port) => {
+                let (x, y) = pad(msg.key)?;
+                Some(PadEvent::Aftertouch{x, y, pressure: msg.velocity})
+            },
+
+            MessageType::CCVelocity if self.has_pads(port) => Some(PadEvent::Pressure(msg.key)),
+
+            MessageType::CC if has_buttons => match (msg.key / 10, msg.key % 10) {
+                (9, col @ 1..=8) => Some(PadEvent::TopButton(Arrow::from_x(col - 1)?, msg.velocity > 0)),
+                (row @ 1..=8, 9) => Some(PadEvent::SideButton(row - 1, msg.velocity > 0)),
+                _ => None
+            },
+
+            _ => None
+        }
+    }
+
+    /// Blocks until a button event comes in on either port, skipping everything else.
+    pub fn recv_event(&self) -> Result<PadEvent, RecvError> {
+        loop {
+            let (port, msg) = self.recv_any(None).map_err(|_| RecvError)?;
+
+            if let Some(event) = self.decode(port, &msg) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Like `recv_event`, giving up after the timeout.
+    pub fn recv_event_timeout(&self, timeout: Duration) -> Result<PadEvent, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (port, msg) = self.recv_any(Some(remaining))?;
+
+            if let Some(event) = self.decode(port, &msg) {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn recv_any(&self, timeout: Option<Duration>) -> Result<(Port, MidiMessage), RecvTimeoutError> {
+        if let Some(msg) = self.held_input.lock().unwrap().pop_front() {
+            return Ok((Port::Midi, msg));
+        }
+
+        let mut select = Select::new();
+        let midi = select.recv(&self.input);
+        let daw = select.recv(&self.daw_input);
+
+        let oper = match timeout {
+            Some(timeout) => select.select_timeout(timeout).map_err(|_| RecvTimeoutError::Timeout)?,
+            None => select.select()
+        };
+
+        let (port, result) = match oper.index() {
+            i if i == midi => (Port::Midi, oper.recv(&self.input)),
+            i if i == daw => (Port::Daw, oper.recv(&self.daw_input)),
+            _ => unreachable!()
+        };
+
+        result
+            .map(|msg| (port, msg))
+            .map_err(|_| RecvTimeoutError::Disconnected)
+    }
+}
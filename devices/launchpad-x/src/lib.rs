@@ -1,11 +1,17 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::iter::once;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crossbeam_channel::{Sender, Receiver};
 use midichan_core::message::{MidiMessage, MessageType};
 
 pub const BYTE_HEADER: [u8; 6] = [0xF0, 0x00, 0x20, 0x29, 0x02, 0x0C];
 
+/// Time to wait for the answer to a query.
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
 
 macro_rules! led_index {
     ($x: expr, $y: expr) => ( ($y+1) * 10 + ($x+1) )
@@ -19,7 +25,12 @@ macro_rules! lpx_color {
     ($x:expr, pulse) => { ::launchpad_x::Color { color: $x, pulse_mode: ::launchpad_x::PulseMode::Pulse }};
 }
 
-#[derive(Clone, Copy)]
+pub mod canvas;
+pub mod event;
+pub use canvas::{Canvas, Cell};
+pub use event::{PadEvent, Arrow, Port};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u8)]
 pub enum PulseMode {
     Static = 0x00,
@@ -27,7 +38,7 @@ pub enum PulseMode {
     Pulse = 0x02,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Color {
     pub color: u8,
     pub pulse_mode: PulseMode
@@ -35,13 +46,38 @@ pub struct Color {
 
 /// Sent out by the Sysex color setter message
 /// Each color is 7-bit.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct LargeColor {
     pub red: u8,
     pub green: u8,
     pub blue: u8
 }
 
+/// Colorspecs in a single LED lighting message, at most.
+pub const MAX_COLOR_SPECS: usize = 81;
+
+/// One LED of the LED lighting SysEx.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpec {
+    Static(u8),
+    /// Flashes between the first and second palette color.
+    Flash(u8, u8),
+    Pulse(u8),
+    Rgb(LargeColor)
+}
+
+impl ColorSpec {
+    /// Type, LED index and data.
+    fn encode(&self, led: u8, out: &mut Vec<u8>) {
+        match *self {
+            ColorSpec::Static(color) => out.extend_from_slice(&[0x00, led, color & 0x7F]),
+            ColorSpec::Flash(first, second) => out.extend_from_slice(&[0x01, led, first & 0x7F, second & 0x7F]),
+            ColorSpec::Pulse(color) => out.extend_from_slice(&[0x02, led, color & 0x7F]),
+            ColorSpec::Rgb(color) => out.extend_from_slice(&[0x03, led, color.red & 0x7F, color.green & 0x7F, color.blue & 0x7F])
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum LaunchpadScreen {
@@ -55,6 +91,67 @@ pub enum LaunchpadScreen {
     Programmer = 0x7F
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum AftertouchMode {
+    Polyphonic = 0x00,
+    Channel = 0x01,
+    Off = 0x02
+}
+
+impl AftertouchMode {
+    fn from_u8(value: u8) -> Option<AftertouchMode> {
+        match value {
+            0x00 => Some(AftertouchMode::Polyphonic),
+            0x01 => Some(AftertouchMode::Channel),
+            0x02 => Some(AftertouchMode::Off),
+            _ => None
+        }
+    }
+}
+
+/// Pressure needed before aftertouch is sent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum AftertouchThreshold {
+    Low = 0x00,
+    Medium = 0x01,
+    High = 0x02
+}
+
+impl AftertouchThreshold {
+    fn from_u8(value: u8) -> Option<AftertouchThreshold> {
+        match value {
+            0x00 => Some(AftertouchThreshold::Low),
+            0x01 => Some(AftertouchThreshold::Medium),
+            0x02 => Some(AftertouchThreshold::High),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum VelocityCurve {
+    Low = 0x00,
+    Medium = 0x01,
+    High = 0x02,
+    /// Every press has the fixed velocity.
+    Fixed = 0x03
+}
+
+impl VelocityCurve {
+    fn from_u8(value: u8) -> Option<VelocityCurve> {
+        match value {
+            0x00 => Some(VelocityCurve::Low),
+            0x01 => Some(VelocityCurve::Medium),
+            0x02 => Some(VelocityCurve::High),
+            0x03 => Some(VelocityCurve::Fixed),
+            _ => None
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LaunchpadX {
     daw_name: String,
@@ -64,6 +161,8 @@ pub struct LaunchpadX {
     output: Sender<MidiMessage>,
     daw_input: Receiver<MidiMessage>,
     daw_output: Sender<MidiMessage>,
+    /// MIDI port input that arrived while waiting for the answer to a query.
+    held_input: Arc<Mutex<VecDeque<MidiMessage>>>,
 
     is_programmer_mode: bool,
     daw_mode: LaunchpadScreen
@@ -79,6 +178,7 @@ impl LaunchpadX {
             daw_name: "Launchpad DAW".to_string(),
             midi_name: "Launchpad MIDI".to_string(),
             input, output, daw_input, daw_output,
+            held_input: Arc::new(Mutex::new(VecDeque::new())),
 
             is_programmer_mode: true,
             daw_mode: LaunchpadScreen::Session
@@ -95,10 +195,17 @@ impl LaunchpadX {
         self
     }
 
+    /// Messages held back by queries are not on it, see `take_held_input`.
     pub fn input(&self) -> Receiver<MidiMessage> {
         self.input.clone()
     }
 
+    /// MIDI port input that arrived while waiting for the answer to a query,
+    /// for callers reading `input` themselves. `recv_event` goes through these first.
+    pub fn take_held_input(&self) -> Vec<MidiMessage> {
+        self.held_input.lock().unwrap().drain(..).collect()
+    }
+
     pub fn output(&self) -> Sender<MidiMessage> {
         self.output.clone()
     }
@@ -130,6 +237,28 @@ impl LaunchpadX {
         )?)
     }
 
+    /// Sends the query, and returns the data of the answer with the same command.
+    ///
+    /// Anything else arriving on the MIDI port in the meantime is held back.
+    fn query_sysex(&self, query: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.send_sysex(query)?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let msg = self.input.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|_| midichan_core::Error::Timeout(self.midi_name.clone()))?;
+
+            if let Some(sysex) = msg.sysex.as_ref() {
+                if sysex.starts_with(&BYTE_HEADER) && sysex.get(BYTE_HEADER.len()) == query.first() {
+                    let end = sysex.len() - (sysex.last() == Some(&0xF7)) as usize;
+                    return Ok(sysex[BYTE_HEADER.len() + 1..end].to_vec());
+                }
+            }
+
+            self.held_input.lock().unwrap().push_back(msg);
+        }
+    }
+
     pub fn send_daw_sysex(&self, sysex: &[u8]) -> Result<(), Box<dyn Error>> {
         Ok(self.daw_output.send(
             MidiMessage{
@@ -199,13 +328,19 @@ impl LaunchpadX {
 
     /// Programmer mode only.
     pub fn set_large(&self, x: u8, y: u8, color: LargeColor) -> Result<(), Box<dyn Error>> {
-        self.send_sysex(&[
-            0x03,
-            led_index!(x, y),
-            color.red,
-            color.green,
-            color.blue
-        ])?;
+        self.set_many(&[((x, y), ColorSpec::Rgb(color))])
+    }
+
+    /// Sets the pads with as few LED lighting messages as possible. Programmer mode only.
+    pub fn set_many(&self, entries: &[((u8, u8), ColorSpec)]) -> Result<(), Box<dyn Error>> {
+        for chunk in entries.chunks(MAX_COLOR_SPECS) {
+            let mut specs = vec![0x03];
+            for ((x, y), spec) in chunk {
+                spec.encode(led_index!(x, y), &mut specs);
+            }
+
+            self.send_sysex(specs.as_slice())?;
+        }
 
         Ok(())
     }
@@ -387,6 +522,53 @@ impl LaunchpadX {
             should_sleep as u8
         ])
     }
+
+    /// Pressure comes in as `PadEvent::Aftertouch` or `PadEvent::Pressure`.
+    pub fn set_aftertouch(&self, mode: AftertouchMode, threshold: AftertouchThreshold) -> Result<(), Box<dyn Error>> {
+        self.send_sysex(&[
+            0x0B,
+            mode as u8,
+            threshold as u8
+        ])
+    }
+
+    /// Asks the device for the current aftertouch settings.
+    ///
+    /// Input arriving meanwhile is held back, for `recv_event` or `take_held_input`.
+    pub fn aftertouch(&self) -> Result<(AftertouchMode, AftertouchThreshold), Box<dyn Error>> {
+        let settings = match self.query_sysex(&[0x0B])?.as_slice() {
+            [mode, threshold, ..] => AftertouchMode::from_u8(*mode).zip(AftertouchThreshold::from_u8(*threshold)),
+            _ => None
+        };
+
+        settings.ok_or_else(|| midichan_core::Error::Desync(self.midi_name.clone()).into())
+    }
+
+    /// The fixed velocity, 1-127, only applies to the fixed curve.
+    pub fn set_velocity_curve(&self, curve: VelocityCurve, fixed_velocity: u8) -> Result<(), Box<dyn Error>> {
+        self.send_sysex(&[
+            0x04,
+            curve as u8,
+            fixed_velocity.clamp(1, 127)
+        ])
+    }
+
+    /// Switches to the fixed curve, with the velocity.
+    pub fn set_fixed_velocity(&self, velocity: u8) -> Result<(), Box<dyn Error>> {
+        self.set_velocity_curve(VelocityCurve::Fixed, velocity)
+    }
+
+    /// Asks the device for the current curve and fixed velocity.
+    ///
+    /// Input arriving meanwhile is held back, for `recv_event` or `take_held_input`.
+    pub fn velocity_curve(&self) -> Result<(VelocityCurve, u8), Box<dyn Error>> {
+        let settings = match self.query_sysex(&[0x04])?.as_slice() {
+            [curve, fixed_velocity, ..] => VelocityCurve::from_u8(*curve).map(|curve| (curve, *fixed_velocity)),
+            _ => None
+        };
+
+        settings.ok_or_else(|| midichan_core::Error::Desync(self.midi_name.clone()).into())
+    }
 }
 
 impl Drop for LaunchpadX {
@@ -4,6 +4,17 @@ use std::cmp::{min};
 use crossbeam_channel::{Sender, Receiver};
 use midichan_core::message::{MidiMessage, MessageType};
 
+/// Writes the LED to both buffers.
+const FLAG_COPY: u8 = 0x04;
+/// Clears the LED in the other buffer.
+const FLAG_CLEAR: u8 = 0x08;
+
+/// First CC of the top row.
+const TOP_CC: u8 = 0x68;
+/// First note of the side column in drum rack mapping, top to bottom.
+const DRUM_SIDE_NOTE: u8 = 100;
+
+/// Two bits each of red and green, with the buffer flags.
 #[derive(Clone)]
 pub struct Color {
     val: u8
@@ -14,21 +25,153 @@ impl Color {
         self.val
     }
 
+    /// Brightness 0-3 each. Written to the updated buffer only.
     pub fn new(red: u8, green: u8) -> Color {
-        Color{val: red + green * 0x10}
+        Color{val: (red & 0x03) | (green & 0x03) << 4}
+    }
+
+    /// Lit in the updated buffer, off in the other one. Blinks while flashing.
+    pub fn flashing(red: u8, green: u8) -> Color {
+        let mut color = Color::new(red, green);
+        color.with_clear(true);
+        color
     }
 
+    pub fn red(&self) -> u8 {
+        self.val & 0x03
+    }
+
+    pub fn green(&self) -> u8 {
+        (self.val >> 4) & 0x03
+    }
+
+    /// Keeps the flags.
     pub fn with_color(&mut self, red: u8, green: u8) -> &mut Color {
-        self.val = self.val & !0x63 + red + green*0x20;
+        self.val = (self.val & !0x33) | (red & 0x03) | (green & 0x03) << 4;
+        self
+    }
+
+    /// Writes to both buffers.
+    pub fn with_copy(&mut self, copy: bool) -> &mut Color {
+        self.val = match copy {
+            true => self.val | FLAG_COPY,
+            false => self.val & !FLAG_COPY
+        };
+        self
+    }
+
+    /// Turns the LED off in the buffer not being updated.
+    pub fn with_clear(&mut self, clear: bool) -> &mut Color {
+        self.val = match clear {
+            true => self.val | FLAG_CLEAR,
+            false => self.val & !FLAG_CLEAR
+        };
+        self
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Buffer {
+    #[default]
+    First,
+    Second
+}
+
+impl Buffer {
+    pub fn other(self) -> Buffer {
+        match self {
+            Buffer::First => Buffer::Second,
+            Buffer::Second => Buffer::First
+        }
+    }
+}
+
+/// Which buffer is shown and which one is written to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BufferControl {
+    pub display: Buffer,
+    pub update: Buffer,
+    /// Keep swapping the displayed buffer, at the flash rate.
+    pub is_flashing: bool,
+    /// Copy the new displayed buffer into the new updated one.
+    pub is_copying: bool
+}
+
+impl BufferControl {
+    pub fn new(display: Buffer, update: Buffer) -> BufferControl {
+        BufferControl{display, update, is_flashing: false, is_copying: false}
+    }
+
+    pub fn with_flashing(&mut self, is_flashing: bool) -> &mut BufferControl {
+        self.is_flashing = is_flashing;
         self
     }
+
+    pub fn with_copying(&mut self, is_copying: bool) -> &mut BufferControl {
+        self.is_copying = is_copying;
+        self
+    }
+
+    /// Data of the CC 0 message.
+    pub fn value(&self) -> u8 {
+        0x20
+            | (self.is_copying as u8) << 4
+            | (self.is_flashing as u8) << 3
+            | (self.update as u8) << 2
+            | self.display as u8
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum GridMapping {
+    /// Note 0x10 * y + x.
+    #[default]
+    XY = 0x01,
+    /// Notes 36-67 on the left half and 68-99 on the right, four per row from the bottom.
+    DrumRack = 0x02
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Brightness {
+    Low = 0x7D,
+    Medium = 0x7E,
+    Full = 0x7F
+}
+
+/// The top row is y = 8, the side column is x = 8, from the top.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Grid{x: u8, y: u8},
+    Side(u8),
+    Top(u8)
+}
+
+impl Button {
+    /// Coordinates as taken by `Launchpad::set`.
+    pub fn position(&self) -> (u8, u8) {
+        match *self {
+            Button::Grid{x, y} => (x, y),
+            Button::Side(y) => (8, y),
+            Button::Top(x) => (x, 8)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonEvent {
+    Press(Button),
+    Release(Button)
 }
 
 #[derive(Clone)]
 pub struct Launchpad {
     name: String,
     input: Receiver<MidiMessage>,
-    output: Sender<MidiMessage>
+    output: Sender<MidiMessage>,
+
+    mapping: GridMapping
 }
 
 impl Launchpad {
@@ -36,7 +179,9 @@ impl Launchpad {
         Launchpad{
             name: "Launchpad".to_string(),
             input: in_port,
-            output: out_port
+            output: out_port,
+
+            mapping: GridMapping::XY
         }
     }
 
@@ -53,79 +198,141 @@ impl Launchpad {
         self.output.clone()
     }
 
-    pub fn clear(&self) -> Result <(), Box<dyn Error>> {
+    fn send(&self, channel: u8, msg_type: MessageType, key: u8, velocity: u8) -> Result<(), Box<dyn Error>> {
         Ok(self.output.send(
             MidiMessage{
                 device: self.name.clone(),
                 timestamp: 0,
-                channel: 0,
-                msg_type: MessageType::CC,
-                key: 0,
-                velocity: 0,
+                channel,
+                msg_type,
+                key,
+                velocity,
                 sysex: None
             }
         )?)
     }
 
-    pub fn set(&self, x: u8, y: u8, color: &Color) -> Result<(), Box<dyn Error>> {
-        Ok(self.output.send(
-            MidiMessage{
-                device: self.name.clone(),
-                timestamp: 0,
-                channel: 0,
-                msg_type: match y {
-                    8 => MessageType::CC,
-                    _ => MessageType::NoteOn
-                },
-                key: match y {
-                    8 => 0x68 + x,
-                    _ => y * 0x10 + x
+    /// Resets the LEDs, buffers and duty cycle. Keeps the grid mapping.
+    pub fn clear(&self) -> Result <(), Box<dyn Error>> {
+        self.send(0, MessageType::CC, 0, 0)?;
+
+        if self.mapping != GridMapping::XY {
+            self.send(0, MessageType::CC, 0, self.mapping as u8)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn mapping(&self) -> GridMapping {
+        self.mapping
+    }
+
+    /// Changes the notes of the grid and side buttons, both for `set` and incoming presses.
+    pub fn set_mapping(&mut self, mapping: GridMapping) -> Result<(), Box<dyn Error>> {
+        self.send(0, MessageType::CC, 0, mapping as u8)?;
+        self.mapping = mapping;
+        Ok(())
+    }
+
+    /// Message type and key of the button at the coordinates.
+    fn key(&self, x: u8, y: u8) -> (MessageType, u8) {
+        match (self.mapping, x, y) {
+            (_, _, 8) => (MessageType::CC, TOP_CC + x),
+            (GridMapping::XY, _, _) => (MessageType::NoteOn, y * 0x10 + x),
+            (GridMapping::DrumRack, 8, _) => (MessageType::NoteOn, DRUM_SIDE_NOTE + y),
+            (GridMapping::DrumRack, _, _) => (MessageType::NoteOn, 36 + (x / 4) * 32 + (7 - y) * 4 + x % 4)
+        }
+    }
+
+    /// The button a message is about, with the current mapping.
+    fn button(&self, msg: &MidiMessage) -> Option<Button> {
+        match (&msg.msg_type, self.mapping, msg.key) {
+            (MessageType::CC, _, key) if (TOP_CC..TOP_CC + 8).contains(&key) => Some(Button::Top(key - TOP_CC)),
+
+            (MessageType::NoteOn, GridMapping::XY, key) | (MessageType::NoteOff, GridMapping::XY, key) => match (key % 0x10, key / 0x10) {
+                (8, y) if y < 8 => Some(Button::Side(y)),
+                (x, y) if x < 8 && y < 8 => Some(Button::Grid{x, y}),
+                _ => None
+            },
+
+            (MessageType::NoteOn, GridMapping::DrumRack, key) | (MessageType::NoteOff, GridMapping::DrumRack, key) => match key {
+                36..=99 => {
+                    let (half, index) = ((key - 36) / 32, (key - 36) % 32);
+                    Some(Button::Grid{x: half * 4 + index % 4, y: 7 - index / 4})
                 },
-                velocity: color.color(),
-                sysex: None
-            }
-        )?)
+                _ if (DRUM_SIDE_NOTE..DRUM_SIDE_NOTE + 8).contains(&key) => Some(Button::Side(key - DRUM_SIDE_NOTE)),
+                _ => None
+            },
+
+            _ => None
+        }
+    }
+
+    /// None for messages that aren't button presses.
+    pub fn decode(&self, msg: &MidiMessage) -> Option<ButtonEvent> {
+        let button = self.button(msg)?;
+
+        match (&msg.msg_type, msg.velocity) {
+            (MessageType::NoteOff, _) | (_, 0) => Some(ButtonEvent::Release(button)),
+            _ => Some(ButtonEvent::Press(button))
+        }
+    }
+
+    pub fn set(&self, x: u8, y: u8, color: &Color) -> Result<(), Box<dyn Error>> {
+        let (msg_type, key) = self.key(x, y);
+        self.send(0, msg_type, key, color.color())
+    }
+
+    pub fn set_buffers(&self, control: &BufferControl) -> Result<(), Box<dyn Error>> {
+        self.send(0, MessageType::CC, 0, control.value())
+    }
+
+    /// Shows the buffer, and updates the other one.
+    pub fn show_buffer(&self, display: Buffer) -> Result<(), Box<dyn Error>> {
+        self.set_buffers(&BufferControl::new(display, display.other()))
+    }
+
+    /// Lights every LED, resetting everything else.
+    pub fn test_leds(&self, brightness: Brightness) -> Result<(), Box<dyn Error>> {
+        self.send(0, MessageType::CC, 0, brightness as u8)
+    }
+
+    /// LED brightness, as the fraction of time they are on.
+    ///
+    /// Numerator 1-16, denominator 3-18, the default is 1/5.
+    pub fn set_duty_cycle(&self, numerator: u8, denominator: u8) -> Result<(), Box<dyn Error>> {
+        let numerator = numerator.clamp(1, 16);
+        let denominator = denominator.clamp(3, 18);
+
+        match numerator {
+            1..=8 => self.send(0, MessageType::CC, 0x1E, (numerator - 1) << 4 | (denominator - 3)),
+            _ => self.send(0, MessageType::CC, 0x1F, (numerator - 9) << 4 | (denominator - 3))
+        }
     }
 
+    /// Two LEDs of the rapid update sequence.
     pub fn fill_step(&self, first: &Color, second: &Color) -> Result<(), Box<dyn Error>> {
-        Ok(self.output.send(
-            MidiMessage{
-                device: self.name.clone(),
-                timestamp: 0,
-                channel: 5,
-                msg_type: MessageType::NoteOn,
-                key: first.color(),
-                velocity: second.color(),
-                sysex: None
-            }
-        )?)
+        self.send(2, MessageType::NoteOn, first.color(), second.color())
     }
 
-    pub fn fill(&self, grid: Vec<Vec<Color>>) -> Result<(), Box<dyn Error>> {
-        let mut temp = Color::new(0, 0);
-        let mut has_val = false;
-        for x_ind in 0..grid.len() {
-            for y_ind in 0..min(grid[x_ind].len(), 8) {
-                if has_val {
-                    self.fill_step(&temp, &grid[x_ind][y_ind])?;
-                } else {
-                    temp = grid[x_ind][y_ind].clone();
-                }
-                has_val = !has_val;
-            }
+    /// Rapid update of the grid, `grid[y][x]` row by row from the top, then the side column and the top row.
+    ///
+    /// Missing LEDs are turned off. Any other message restarts the sequence from the top left.
+    pub fn fill(&self, grid: Vec<Vec<Color>>, side: &[Color], top: &[Color]) -> Result<(), Box<dyn Error>> {
+        let off = Color::new(0, 0);
 
-            for _ in grid[x_ind].len()..8 {
-                if has_val {
-                    self.fill_step(&temp, &Color::new(0, 0))?;
-                } else {
-                    temp = Color::new(0, 0);
-                }
-                has_val = !has_val;
-            }
+        let mut colors = Vec::with_capacity(80);
+        for y_ind in 0..8 {
+            let row = grid.get(y_ind).map(|row| &row[..min(row.len(), 8)]).unwrap_or(&[]);
+            colors.extend((0..8).map(|x_ind| row.get(x_ind).unwrap_or(&off)));
         }
+        colors.extend((0..8).map(|y_ind| side.get(y_ind).unwrap_or(&off)));
+        colors.extend((0..8).map(|x_ind| top.get(x_ind).unwrap_or(&off)));
 
-        // TODO Sidebar and top bar
+        for pair in colors.chunks(2) {
+            self.fill_step(pair[0], pair[1])?;
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,26 @@
 #[macro_use]
 mod macros;
 
+mod output;
+pub mod targets;
+pub mod transform;
+
+use std::ops::RangeInclusive;
 use std::time::Duration;
-use std::error::Error;
+use std::error;
 use std::thread;
 
 use hashbrown::HashMap;
-use crossbeam_channel::{bounded, Sender, Receiver, Select};
+use crossbeam_channel::{bounded, Sender, Receiver, RecvTimeoutError, Select};
 
-use midichan_core::message::{RouterRequest, RouterResponse, MidiMessage};
+use midichan_core::message::{RouterRequest, RouterResponse, MidiMessage, MessageType, OutputPolicy, RouteStats, RouterStats};
+use midichan_core::Error;
 use midichan_core::device::{Controllable, RoutingDevice};
 
+use crate::output::Output;
+
+pub use midichan_core::device::{RoutingFunction, Transform};
+
 const TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct Router {
@@ -19,15 +29,16 @@ pub struct Router {
 }
 
 impl Router {
-    pub fn with_function  <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<String>>
-                        (router_func: T) -> Router {
+    pub fn with_function<T: 'static + RoutingFunction>(router_func: T) -> Router {
+        Router::with_boxed_function(Box::new(router_func))
+    }
+
+    pub fn with_boxed_function(router_func: Box<dyn RoutingFunction>) -> Router {
         let (exported_send, thread_recv) = bounded(0);
         let (thread_send, exported_recv) = bounded(2);
 
-        let cloned_func = router_func;
-
         thread::spawn(move || {
-            router_wrapper(cloned_func, thread_recv, thread_send);
+            router_wrapper(router_func, thread_recv, thread_send);
         });
 
         Router{control_request: exported_send, control_response: exported_recv}
@@ -52,6 +63,47 @@ impl Router {
         Router::with_function(on_off_func)
     }
 
+    /// Notes go to every output whose key range contains them, everything else to all outputs.
+    pub fn note_ranges(ranges: Vec<(RangeInclusive<u8>, String)>) -> Router {
+        Router::with_function(move |msg: &mut MidiMessage| {
+            match msg.msg_type {
+                MessageType::NoteOn | MessageType::NoteOff | MessageType::NoteVelocity => ranges.iter()
+                    .filter(|(range, _)| range.contains(&msg.key))
+                    .map(|(_, name)| name.clone())
+                    .collect(),
+
+                _ => vec!("all".to_string())
+            }
+        })
+    }
+
+    /// Note ons go to the layers whose velocity range contains them,
+    /// and the rest of the note to the same layers.
+    pub fn velocity_layers(layers: Vec<(RangeInclusive<u8>, String)>) -> Router {
+        let mut held: HashMap<(u8, u8), Vec<String>> = HashMap::new();
+
+        Router::with_function(move |msg: &mut MidiMessage| {
+            let note = (msg.channel, msg.key);
+
+            match msg.msg_type {
+                MessageType::NoteOn if msg.velocity > 0 => {
+                    let targets: Vec<String> = layers.iter()
+                        .filter(|(range, _)| range.contains(&msg.velocity))
+                        .map(|(_, name)| name.clone())
+                        .collect();
+
+                    held.insert(note, targets.clone());
+                    targets
+                },
+
+                MessageType::NoteOn | MessageType::NoteOff => held.remove(&note).unwrap_or_default(),
+                MessageType::NoteVelocity => held.get(&note).cloned().unwrap_or_default(),
+
+                _ => vec!("all".to_string())
+            }
+        })
+    }
+
     pub fn mirror_all() -> Router {
         fn all_func(_dev: &mut MidiMessage) -> Vec<String> {
             vec!("all".to_string())
@@ -72,59 +124,63 @@ impl Controllable<RouterRequest, RouterResponse> for Router {
 }
 
 impl RoutingDevice for Router {
-    fn add_input(&self, name: String, port: Receiver<MidiMessage>) -> Result<(), String> {
+    fn add_input(&self, name: String, port: Receiver<MidiMessage>) -> Result<(), Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::AddInput(name, port), "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Ok) => Ok(()),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn add_output(&self, name: String, port: Sender<MidiMessage>) -> Result<(), String> {
+    fn add_output(&self, name: String, port: Sender<MidiMessage>) -> Result<(), Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::AddOutput(name, port), "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Ok) => Ok(()),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn remove_input(&self, name: String) -> Result<(), String> {
+    fn remove_input(&self, name: String) -> Result<(), Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::RemoveInput(name), "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Ok) => Ok(()),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn remove_output(&self, name: String) -> Result<(), String> {
+    fn remove_output(&self, name: String) -> Result<(), Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::RemoveOutput(name), "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Ok) => Ok(()),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn query_input(&self, name: String) -> Result<bool, String>{
+    fn query_input(&self, name: String) -> Result<bool, Error>{
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::QueryInput(name), "router");
         // self.control_request.send(
@@ -132,14 +188,15 @@ impl RoutingDevice for Router {
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Device(_, status))  => Ok(status),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
     
-    fn query_output(&self, name: String) -> Result<bool, String>{
+    fn query_output(&self, name: String) -> Result<bool, Error>{
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::QueryOutput(name), "router");
         // self.control_request.send(
@@ -147,45 +204,118 @@ impl RoutingDevice for Router {
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::Device(_, status))  => Ok(status),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn query_all_inputs(&self) -> Result<Vec<String>, String> {
+    fn query_all_inputs(&self) -> Result<Vec<String>, Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::QueryAllInputs, "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::List(list)) => Ok(list),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn query_all_outputs(&self) -> Result<Vec<String>, String> {
+    fn query_all_outputs(&self) -> Result<Vec<String>, Error> {
         error_on_full!(self.control_response, "router");
         send_or_err!(self.control_request, RouterRequest::QueryAllOutputs, "router");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(RouterResponse::List(list)) => Ok(list),
-            Ok(RouterResponse::Error(err)) => Err(err.to_string()),
+            Ok(RouterResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("router desync".to_string()),
-            Err(_) => Err("router timed out".to_string())
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
         }
     }
 
-    fn query_all(&self) -> Result<(Vec<String>, Vec<String>), String> {
+    fn query_all(&self) -> Result<(Vec<String>, Vec<String>), Error> {
         let inputs = self.query_all_inputs()?;
         let outputs = self.query_all_outputs()?;
 
         Ok((inputs, outputs))
     }
+
+    fn set_function(&self, router_func: Box<dyn RoutingFunction>) -> Result<(), Error> {
+        error_on_full!(self.control_response, "router");
+        send_or_err!(self.control_request, RouterRequest::SetFunction(router_func), "router");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(RouterResponse::Ok) => Ok(()),
+            Ok(RouterResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
+        }
+    }
+
+    fn set_transform(&self, output: String, transform: Box<dyn Transform>) -> Result<(), Error> {
+        error_on_full!(self.control_response, "router");
+        send_or_err!(self.control_request, RouterRequest::SetTransform(output, transform), "router");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(RouterResponse::Ok) => Ok(()),
+            Ok(RouterResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
+        }
+    }
+
+    fn clear_transform(&self, output: String) -> Result<(), Error> {
+        error_on_full!(self.control_response, "router");
+        send_or_err!(self.control_request, RouterRequest::ClearTransform(output), "router");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(RouterResponse::Ok) => Ok(()),
+            Ok(RouterResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
+        }
+    }
+
+    fn set_policy(&self, output: String, policy: OutputPolicy) -> Result<(), Error> {
+        error_on_full!(self.control_response, "router");
+        send_or_err!(self.control_request, RouterRequest::SetPolicy(output, policy), "router");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(RouterResponse::Ok) => Ok(()),
+            Ok(RouterResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
+        }
+    }
+
+    fn stats(&self) -> Result<RouterStats, Error> {
+        error_on_full!(self.control_response, "router");
+        send_or_err!(self.control_request, RouterRequest::Stats, "router");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(RouterResponse::Stats(stats)) => Ok(stats),
+            Ok(RouterResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("router".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("router".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("router".to_string()))
+        }
+    }
 }
 
 impl Drop for Router {
@@ -197,8 +327,7 @@ impl Drop for Router {
 }
 
 
-fn router_wrapper  <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<String>>
-                (router_func: T, control_request: Receiver<RouterRequest>, control_response: Sender<RouterResponse>) {
+fn router_wrapper(router_func: Box<dyn RoutingFunction>, control_request: Receiver<RouterRequest>, control_response: Sender<RouterResponse>) {
     match router_thread(router_func, &control_request, &control_response) {
         Ok(()) => {},
 
@@ -209,14 +338,24 @@ fn router_wrapper  <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
     };
 }
 
-fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<String>>
-                (router_func: T, control_request: &Receiver<RouterRequest>, control_response: &Sender<RouterResponse>) -> Result<(), Box<dyn Error>> {
+fn router_thread(mut router_func: Box<dyn RoutingFunction>, control_request: &Receiver<RouterRequest>, control_response: &Sender<RouterResponse>) -> Result<(), Box<dyn error::Error>> {
     let mut in_map = HashMap::new();
-    let mut out_map = HashMap::new();
+    let mut out_map: HashMap<String, Output> = HashMap::new();
+    let mut transforms: HashMap<String, Box<dyn Transform>> = HashMap::new();
+
+    let mut in_stats: HashMap<String, RouteStats> = HashMap::new();
+    let mut out_stats: HashMap<String, RouteStats> = HashMap::new();
 
     let mut select_map = HashMap::new();
+    let mut flush_map = HashMap::new();
 
     loop {
+        // Outputs holding messages back get woken up when their channel has room again.
+        let flushing: Vec<(String, Sender<MidiMessage>)> = out_map.iter()
+            .filter(|(_, output)| !output.backlog.is_empty())
+            .map(|(name, output)| (name.clone(), output.port.clone()))
+            .collect();
+
         let (result, control_id) = {
             let mut select = Select::new();
 
@@ -226,6 +365,11 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
                 select_map.insert(select.recv(input), cloned_name);
             }
 
+            flush_map.clear();
+            for (position, (_, port)) in flushing.iter().enumerate() {
+                flush_map.insert(select.send(port), position);
+            }
+
             let control_id = select.recv(control_request);
 
             let res = select.select();
@@ -238,6 +382,7 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
         if index == control_id {
             match result.recv(control_request)? {
                 RouterRequest::AddInput(name, port) => {
+                    in_stats.insert(name.clone(), RouteStats::default());
                     in_map.insert(name, port);
                     
                     control_response.send(
@@ -245,7 +390,8 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
                 },
 
                 RouterRequest::AddOutput(name, port) => {
-                    out_map.insert(name, port);
+                    out_stats.insert(name.clone(), RouteStats::default());
+                    out_map.insert(name, Output::new(port));
                     
                     control_response.send(
                         RouterResponse::Ok)?;
@@ -253,6 +399,7 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
 
                 RouterRequest::RemoveInput(name) => {
                     in_map.remove(&name);
+                    in_stats.remove(&name);
 
                     control_response.send(
                         RouterResponse::Ok)?;
@@ -260,6 +407,8 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
 
                 RouterRequest::RemoveOutput(name) => {
                     out_map.remove(&name);
+                    out_stats.remove(&name);
+                    transforms.remove(&name);
 
                     control_response.send(
                         RouterResponse::Ok)?;
@@ -284,7 +433,60 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
 
                 RouterRequest::QueryAllOutputs => {
                     control_response.send(
-                        RouterResponse::List(in_map.keys().cloned().collect()))?;
+                        RouterResponse::List(out_map.keys().cloned().collect()))?;
+                },
+
+                RouterRequest::Stats => {
+                    for (name, stats) in out_stats.iter_mut() {
+                        stats.queued = out_map.get(name).map_or(0, |output| output.backlog.len());
+                    }
+
+                    control_response.send(
+                        RouterResponse::Stats(RouterStats {
+                            inputs: in_stats.iter().map(|(name, stats)| (name.clone(), stats.clone())).collect(),
+                            outputs: out_stats.iter().map(|(name, stats)| (name.clone(), stats.clone())).collect()
+                        }))?;
+                },
+
+                RouterRequest::SetFunction(new_func) => {
+                    router_func = new_func;
+
+                    control_response.send(
+                        RouterResponse::Ok)?;
+                },
+
+                RouterRequest::SetTransform(name, transform) => {
+                    if out_map.contains_key(&name) {
+                        transforms.insert(name, transform);
+
+                        control_response.send(
+                            RouterResponse::Ok)?;
+                    } else {
+                        control_response.send(
+                            RouterResponse::Error(Error::PortNotFound(name)))?;
+                    }
+                },
+
+                RouterRequest::ClearTransform(name) => {
+                    transforms.remove(&name);
+
+                    control_response.send(
+                        RouterResponse::Ok)?;
+                },
+
+                RouterRequest::SetPolicy(name, policy) => {
+                    match out_map.get_mut(&name) {
+                        Some(output) => {
+                            output.policy = policy;
+
+                            control_response.send(
+                                RouterResponse::Ok)?;
+                        },
+                        None => {
+                            control_response.send(
+                                RouterResponse::Error(Error::PortNotFound(name)))?;
+                        }
+                    }
                 },
 
                 RouterRequest::Shutdown => {
@@ -293,23 +495,105 @@ fn router_thread   <T: 'static + Send + Copy + Fn(&mut MidiMessage) -> Vec<Strin
                     return Ok(());
                 },
             }
+        } else if let Some(position) = flush_map.get(&index) {
+            let (name, port) = &flushing[*position];
+            let stats = out_stats.entry(name.clone()).or_insert_with(RouteStats::default);
+
+            let output = match out_map.get_mut(name) {
+                Some(output) => output,
+                None => continue
+            };
+
+            if let Some(msg) = output.backlog.pop_front() {
+                if result.send(port, msg).is_ok() {
+                    stats.record();
+                } else {
+                    stats.drops += 1;
+                }
+            }
+
+            output.flush(stats);
         } else {
-            let mut msg = match result.recv(&in_map[&select_map[&index]]) {
+            let input_name = &select_map[&index];
+            let mut msg = match result.recv(&in_map[input_name]) {
                 Ok(msg) => msg,
                 Err(_) => continue
             };
 
-            let targets = router_func(&mut msg);
+            let targets = router_func.route(&mut msg);
+            let mut delivered = false;
 
-            for target in targets {
-                if target == "all" {
-                    for output in out_map.values() {
-                        output.send(msg.clone()).ok();
+            // Each output gets the message once, however many targets match it.
+            let outputs: Vec<String> = out_map.keys()
+                .filter(|name| targets.iter().any(|target| crate::targets::matches(target, input_name, name)))
+                .cloned()
+                .collect();
+
+            for name in outputs {
+                let stats = out_stats.entry(name.clone()).or_insert_with(RouteStats::default);
+
+                let mut out_msg = msg.clone();
+                if let Some(transform) = transforms.get_mut(&name) {
+                    if !transform.apply(&mut out_msg) {
+                        continue;
                     }
-                } else {
-                    out_map.get(&target).map(|x| x.send(msg.clone()));
+                }
+
+                if let Some(output) = out_map.get_mut(&name) {
+                    delivered |= output.send(out_msg, stats);
+                }
+            }
+
+            if let Some(stats) = in_stats.get_mut(input_name) {
+                stats.record();
+                if !delivered {
+                    stats.drops += 1;
                 }
             }
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    #[test]
+    fn stats_with_disconnected_output() {
+        let router = Router::mirror_all();
+        let (input, input_recv) = unbounded();
+        let (synth, synth_recv) = unbounded();
+        let (gone, _) = unbounded();
+
+        router.add_input("keys".to_string(), input_recv).unwrap();
+        router.add_output("synth".to_string(), synth).unwrap();
+        router.add_output("gone".to_string(), gone).unwrap();
+
+        let mut outputs = router.query_all_outputs().unwrap();
+        outputs.sort();
+        assert_eq!(outputs, vec!["gone", "synth"]);
+        assert_eq!(router.query_all_inputs().unwrap(), vec!["keys"]);
+
+        for key in 0..3 {
+            let mut msg = MidiMessage::new("keys");
+            msg.with_key(key);
+            input.send(msg).unwrap();
+        }
+        for _ in 0..3 {
+            synth_recv.recv_timeout(TIMEOUT).unwrap();
+        }
+
+        let stats = router.stats().unwrap();
+        let counts = |routes: &[(String, RouteStats)], name: &str| {
+            routes.iter()
+                .find(|(route, _)| route == name)
+                .map(|(_, stats)| (stats.messages, stats.drops))
+        };
+
+        assert_eq!(counts(&stats.inputs, "keys"), Some((3, 0)));
+        assert_eq!(counts(&stats.outputs, "synth"), Some((3, 0)));
+        assert_eq!(counts(&stats.outputs, "gone"), Some((0, 3)));
+    }
+}
@@ -2,7 +2,7 @@
 macro_rules! send_or_err {
     ($control_request:expr, $msg:expr, $name:expr) => {
         if let Err(_) = $control_request.send($msg) {
-            return Err(format!("{}: channel error", $name));
+            return Err(::midichan_core::Error::ChannelClosed($name.to_string()));
         }
     };
 }
@@ -11,7 +11,7 @@ macro_rules! send_or_err {
 macro_rules! error_on_full {
     ($control_response:expr, $name:expr) => {
         if $control_response.is_full() {
-            return Err(format!("{}: errored out", $name));
+            return Err(::midichan_core::Error::Desync($name.to_string()));
         }
     };
 }
\ No newline at end of file
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use crossbeam_channel::{Sender, TrySendError};
+
+use midichan_core::message::{MidiMessage, MessageType, OutputPolicy, RouteStats};
+
+/// Messages held back per output, before the oldest ones get dropped.
+const BACKLOG: usize = 128;
+
+/// Same LED, same pulse mode. Note offs turn off the LED of the note on.
+fn led_key(msg: &MidiMessage) -> Option<(bool, u8, u8)> {
+    match msg.msg_type {
+        MessageType::NoteOn | MessageType::NoteOff => Some((false, msg.channel, msg.key)),
+        MessageType::CC => Some((true, msg.channel, msg.key)),
+        _ => None
+    }
+}
+
+/// A router output, with the messages its policy is holding back.
+pub(crate) struct Output {
+    pub port: Sender<MidiMessage>,
+    pub policy: OutputPolicy,
+    pub backlog: VecDeque<MidiMessage>
+}
+
+impl Output {
+    pub fn new(port: Sender<MidiMessage>) -> Output {
+        Output{port, policy: OutputPolicy::default(), backlog: VecDeque::new()}
+    }
+
+    /// False if the message was dropped.
+    pub fn send(&mut self, msg: MidiMessage, stats: &mut RouteStats) -> bool {
+        let result = match self.policy {
+            OutputPolicy::Block => self.port.send(msg).map_err(|_| ()),
+            OutputPolicy::DropNewest => self.port.try_send(msg).map_err(|_| ()),
+
+            OutputPolicy::DropOldest | OutputPolicy::CoalesceLeds => {
+                // Once something is held back, newer messages queue up behind it.
+                if !self.backlog.is_empty() {
+                    return self.queue(msg, stats);
+                }
+
+                match self.port.try_send(msg) {
+                    Err(TrySendError::Full(msg)) => return self.queue(msg, stats),
+                    result => result.map_err(|_| ())
+                }
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                stats.record();
+                true
+            },
+            Err(()) => {
+                stats.drops += 1;
+                false
+            }
+        }
+    }
+
+    fn queue(&mut self, msg: MidiMessage, stats: &mut RouteStats) -> bool {
+        if self.policy == OutputPolicy::CoalesceLeds {
+            if let Some(key) = led_key(&msg) {
+                if let Some(queued) = self.backlog.iter_mut().find(|queued| led_key(queued) == Some(key)) {
+                    *queued = msg;
+                    stats.drops += 1;
+                    return true;
+                }
+            }
+        }
+
+        if self.backlog.len() >= BACKLOG {
+            self.backlog.pop_front();
+            stats.drops += 1;
+        }
+
+        self.backlog.push_back(msg);
+        true
+    }
+
+    /// Moves held back messages into the channel, while there is room.
+    pub fn flush(&mut self, stats: &mut RouteStats) {
+        while let Some(msg) = self.backlog.pop_front() {
+            match self.port.try_send(msg) {
+                Ok(()) => stats.record(),
+
+                Err(TrySendError::Full(msg)) => {
+                    self.backlog.push_front(msg);
+                    return;
+                },
+
+                Err(TrySendError::Disconnected(_)) => {
+                    stats.drops += 1 + self.backlog.len() as u64;
+                    self.backlog.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::bounded;
+
+    fn message(msg_type: MessageType, key: u8, velocity: u8) -> MidiMessage {
+        let mut msg = MidiMessage::new("Test");
+        msg.with_msg_type(msg_type).with_key(key).with_velocity(velocity);
+        msg
+    }
+
+    fn keys(backlog: &VecDeque<MidiMessage>) -> Vec<(MessageType, u8, u8)> {
+        backlog.iter().map(|msg| (msg.msg_type.clone(), msg.key, msg.velocity)).collect()
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (send, recv) = bounded(1);
+        let mut output = Output::new(send);
+        output.policy = OutputPolicy::DropNewest;
+        let mut stats = RouteStats::default();
+
+        assert!(output.send(message(MessageType::NoteOn, 1, 1), &mut stats));
+        assert!(!output.send(message(MessageType::NoteOn, 2, 1), &mut stats));
+        assert_eq!((stats.messages, stats.drops), (1, 1));
+
+        assert_eq!(recv.try_recv().unwrap().key, 1);
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (send, recv) = bounded(1);
+        let mut output = Output::new(send);
+        output.policy = OutputPolicy::DropOldest;
+        let mut stats = RouteStats::default();
+
+        for key in 0..=BACKLOG as u8 + 1 {
+            assert!(output.send(message(MessageType::NoteOn, key, 1), &mut stats));
+        }
+        assert_eq!((stats.messages, stats.drops), (1, 1));
+        assert_eq!(output.backlog.len(), BACKLOG);
+        // Key 1 was the oldest one held back.
+        assert_eq!(output.backlog.front().unwrap().key, 2);
+
+        assert_eq!(recv.try_recv().unwrap().key, 0);
+        output.flush(&mut stats);
+        assert_eq!(output.backlog.len(), BACKLOG - 1);
+        assert_eq!(stats.messages, 2);
+
+        // Messages wait for the backlog, even with room in the channel.
+        assert_eq!(recv.try_recv().unwrap().key, 2);
+        output.send(message(MessageType::CC, 0, 0), &mut stats);
+        assert!(recv.try_recv().is_err());
+        assert_eq!(output.backlog.back().unwrap().msg_type, MessageType::CC);
+    }
+
+    #[test]
+    fn coalesce_leds() {
+        let (send, recv) = bounded(1);
+        let mut output = Output::new(send);
+        output.policy = OutputPolicy::CoalesceLeds;
+        let mut stats = RouteStats::default();
+
+        output.send(message(MessageType::NoteOn, 0, 5), &mut stats);
+        output.send(message(MessageType::NoteOn, 11, 5), &mut stats);
+        output.send(message(MessageType::NoteOn, 12, 5), &mut stats);
+        output.send(message(MessageType::CC, 11, 5), &mut stats);
+        output.send(message(MessageType::NoteOff, 11, 0), &mut stats);
+        output.send(message(MessageType::CC, 11, 7), &mut stats);
+        output.send(message(MessageType::PC, 11, 0), &mut stats);
+        output.send(message(MessageType::PC, 11, 0), &mut stats);
+
+        assert_eq!(keys(&output.backlog), vec![
+            (MessageType::NoteOff, 11, 0),
+            (MessageType::NoteOn, 12, 5),
+            (MessageType::CC, 11, 7),
+            (MessageType::PC, 11, 0),
+            (MessageType::PC, 11, 0)
+        ]);
+        assert_eq!((stats.messages, stats.drops), (1, 2));
+
+        recv.try_recv().unwrap();
+        output.flush(&mut stats);
+        assert_eq!(recv.try_recv().unwrap().msg_type, MessageType::NoteOff);
+    }
+
+    #[test]
+    fn disconnected_flush() {
+        let (send, recv) = bounded(1);
+        let mut output = Output::new(send);
+        output.policy = OutputPolicy::DropOldest;
+        let mut stats = RouteStats::default();
+
+        for key in 0..3 {
+            output.send(message(MessageType::NoteOn, key, 1), &mut stats);
+        }
+        drop(recv);
+        output.flush(&mut stats);
+
+        assert!(output.backlog.is_empty());
+        assert_eq!((stats.messages, stats.drops), (1, 2));
+    }
+}
@@ -0,0 +1,100 @@
+//! Output names a routing function can return, besides exact output names.
+//!
+//! - `"all"`: every output.
+//! - `"source"`: the output named like the input the message came from.
+//! - `"launchpad-*"`: outputs matching the glob, `*` for any run of characters and `?` for exactly one.
+//! - `"all-except:<target>"`: every output not matched by `<target>`, e.g. `"all-except:source"`.
+
+pub const ALL: &str = "all";
+pub const SOURCE: &str = "source";
+pub const ALL_EXCEPT: &str = "all-except:";
+
+/// Glob match on the whole name.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last star, if the rest fails to match.
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `target` addresses the output `name`, for a message from the input `source`.
+pub fn matches(target: &str, source: &str, name: &str) -> bool {
+    if target == ALL {
+        true
+    } else if target == SOURCE {
+        name == source
+    } else if let Some(excluded) = target.strip_prefix(ALL_EXCEPT) {
+        !matches(excluded, source, name)
+    } else if target.contains(['*', '?']) {
+        glob_match(target, name)
+    } else {
+        name == target
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUTS: [&str; 4] = ["launchpad-x", "launchpad-mini", "synth", "keys"];
+
+    fn matching(target: &str, source: &str) -> Vec<&'static str> {
+        OUTPUTS.iter().copied().filter(|name| matches(target, source, name)).collect()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("launchpad-*", "launchpad-x"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("launchpad-?", "launchpad-x"));
+        assert!(!glob_match("launchpad-?", "launchpad-mini"));
+        assert!(!glob_match("launchpad", "launchpad-x"));
+        assert!(!glob_match("?", ""));
+
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("a*b*c", "abcbc"));
+        assert!(!glob_match("a*b*c", "aXbYcZ"));
+        assert!(!glob_match("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(matching("all", "keys"), OUTPUTS.to_vec());
+        assert_eq!(matching("source", "keys"), vec!["keys"]);
+        assert_eq!(matching("launchpad-*", "keys"), vec!["launchpad-x", "launchpad-mini"]);
+
+        assert_eq!(matching("all-except:source", "keys"), vec!["launchpad-x", "launchpad-mini", "synth"]);
+        assert_eq!(matching("all-except:launchpad-*", "keys"), vec!["synth", "keys"]);
+        assert_eq!(matching("all-except:all", "keys"), Vec::<&str>::new());
+
+        assert_eq!(matching("drums-*", "keys"), Vec::<&str>::new());
+        assert_eq!(matching("drums", "keys"), Vec::<&str>::new());
+    }
+}
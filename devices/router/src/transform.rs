@@ -0,0 +1,344 @@
+//! Reusable transforms, to set in front of router outputs with `RoutingDevice::set_transform`.
+
+use std::ops::RangeInclusive;
+
+use hashbrown::HashMap;
+
+use midichan_core::message::{MidiMessage, MessageType};
+use midichan_core::device::Transform;
+
+fn is_note(msg: &MidiMessage) -> bool {
+    matches!(msg.msg_type, MessageType::NoteOn | MessageType::NoteOff | MessageType::NoteVelocity)
+}
+
+/// Runs transforms in order, stopping at the first one that drops the message.
+#[derive(Default)]
+pub struct Chain {
+    stages: Vec<Box<dyn Transform>>
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain{stages: Vec::new()}
+    }
+
+    pub fn with_stage<T: 'static + Transform>(&mut self, stage: T) -> &mut Chain {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn with_boxed_stage(&mut self, stage: Box<dyn Transform>) -> &mut Chain {
+        self.stages.push(stage);
+        self
+    }
+}
+
+impl Transform for Chain {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        self.stages.iter_mut().all(|stage| stage.apply(msg))
+    }
+}
+
+/// Shifts notes by semitones, dropping the ones pushed out of the MIDI range.
+pub struct Transpose(pub i8);
+
+impl Transform for Transpose {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        if !is_note(msg) {
+            return true;
+        }
+
+        let key = msg.key as i16 + self.0 as i16;
+        if (0..=127).contains(&key) {
+            msg.key = key as u8;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Moves channel messages to other channels. Unmapped channels are left alone.
+pub struct ChannelRemap {
+    map: [u8; 16]
+}
+
+impl ChannelRemap {
+    pub fn new() -> ChannelRemap {
+        let mut map = [0; 16];
+        for (channel, target) in map.iter_mut().enumerate() {
+            *target = channel as u8;
+        }
+
+        ChannelRemap{map}
+    }
+
+    /// Everything ends up on `channel`.
+    pub fn all_to(channel: u8) -> ChannelRemap {
+        ChannelRemap{map: [channel & 0x0F; 16]}
+    }
+
+    pub fn with_channel(&mut self, from: u8, to: u8) -> &mut ChannelRemap {
+        self.map[(from & 0x0F) as usize] = to & 0x0F;
+        self
+    }
+}
+
+impl Default for ChannelRemap {
+    fn default() -> ChannelRemap {
+        ChannelRemap::new()
+    }
+}
+
+impl Transform for ChannelRemap {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        if msg.msg_type.is_channel() {
+            msg.channel = self.map[(msg.channel & 0x0F) as usize];
+        }
+
+        true
+    }
+}
+
+/// Reshapes note on velocities. Note offs, including note ons with zero velocity, are left alone.
+#[derive(Clone, Debug)]
+pub enum VelocityCurve {
+    /// Scales 1..=127 to `min..=max`.
+    Linear { min: u8, max: u8 },
+    /// Raises the velocity to this power: above 1 softens, below 1 hardens.
+    Exponential(f32),
+    Fixed(u8)
+}
+
+impl VelocityCurve {
+    pub fn velocity(&self, velocity: u8) -> u8 {
+        let velocity = velocity.clamp(1, 127);
+
+        let curved = match *self {
+            VelocityCurve::Linear { min, max } =>
+                min as i32 + (velocity as i32 - 1) * (max as i32 - min as i32) / 126,
+            VelocityCurve::Exponential(exponent) =>
+                ((velocity as f32 / 127.0).powf(exponent) * 127.0).round() as i32,
+            VelocityCurve::Fixed(fixed) => fixed as i32
+        };
+
+        curved.clamp(1, 127) as u8
+    }
+}
+
+impl Transform for VelocityCurve {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        if msg.msg_type == MessageType::NoteOn && msg.velocity > 0 {
+            msg.velocity = self.velocity(msg.velocity);
+        }
+
+        true
+    }
+}
+
+/// Changes the controller number of CCs. Unmapped controllers are left alone.
+#[derive(Default)]
+pub struct CcRenumber {
+    map: HashMap<u8, u8>
+}
+
+impl CcRenumber {
+    pub fn new() -> CcRenumber {
+        CcRenumber{map: HashMap::new()}
+    }
+
+    pub fn with_cc(&mut self, from: u8, to: u8) -> &mut CcRenumber {
+        self.map.insert(from & 0x7F, to & 0x7F);
+        self
+    }
+}
+
+impl Transform for CcRenumber {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        if msg.msg_type == MessageType::CC {
+            if let Some(cc) = self.map.get(&msg.key) {
+                msg.key = *cc;
+            }
+        }
+
+        true
+    }
+}
+
+/// Turns notes into CCs with the same number, the value being the velocity.
+/// Note offs send a zero value, aftertouch is dropped.
+pub struct NoteToCc;
+
+impl Transform for NoteToCc {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        match msg.msg_type {
+            MessageType::NoteOn => {
+                msg.msg_type = MessageType::CC;
+                true
+            },
+            MessageType::NoteOff => {
+                msg.msg_type = MessageType::CC;
+                msg.velocity = 0;
+                true
+            },
+            MessageType::NoteVelocity => false,
+            _ => true
+        }
+    }
+}
+
+/// Only lets notes in the range through, everything else passes.
+pub struct KeyRange(pub RangeInclusive<u8>);
+
+impl Transform for KeyRange {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        !is_note(msg) || self.0.contains(&msg.key)
+    }
+}
+
+/// Drops channel messages that repeat the last value sent to the same note, controller or channel.
+/// Everything else passes.
+#[derive(Default)]
+pub struct Dedup {
+    last: HashMap<(u8, u8), (u8, u8)>
+}
+
+impl Dedup {
+    pub fn new() -> Dedup {
+        Dedup{last: HashMap::new()}
+    }
+
+    /// Forgets every value seen so far.
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+}
+
+impl Transform for Dedup {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        if !msg.msg_type.is_channel() {
+            return true;
+        }
+
+        let is_keyed = is_note(msg) || msg.msg_type == MessageType::CC;
+        // Note ons and offs share a slot, so a note off after a note on is never dropped.
+        let status = match msg.msg_type {
+            MessageType::NoteOff => MessageType::NoteOn as u8,
+            ref msg_type => msg_type.clone() as u8
+        } | (msg.channel & 0x0F);
+        let velocity = if msg.msg_type == MessageType::NoteOff { 0 } else { msg.velocity };
+
+        let slot = (status, if is_keyed { msg.key } else { 0 });
+        let value = (msg.key, velocity);
+
+        self.last.insert(slot, value) != Some(value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn message(msg_type: MessageType, channel: u8, key: u8, velocity: u8) -> MidiMessage {
+        let mut msg = MidiMessage::new("Test");
+        msg.with_msg_type(msg_type).with_channel(channel).with_key(key).with_velocity(velocity);
+        msg
+    }
+
+    #[test]
+    fn velocity_curves() {
+        let linear = VelocityCurve::Linear { min: 20, max: 100 };
+        assert_eq!(linear.velocity(1), 20);
+        assert_eq!(linear.velocity(64), 60);
+        assert_eq!(linear.velocity(127), 100);
+        assert_eq!(VelocityCurve::Linear { min: 0, max: 127 }.velocity(1), 1);
+
+        assert_eq!(VelocityCurve::Exponential(2.0).velocity(64), 32);
+        assert_eq!(VelocityCurve::Exponential(0.5).velocity(32), 64);
+        assert_eq!(VelocityCurve::Exponential(1.0).velocity(100), 100);
+        assert_eq!(VelocityCurve::Exponential(2.0).velocity(127), 127);
+        assert_eq!(VelocityCurve::Exponential(4.0).velocity(1), 1);
+
+        let mut fixed = VelocityCurve::Fixed(90);
+        let mut note_on = message(MessageType::NoteOn, 0, 60, 10);
+        let mut silent_on = message(MessageType::NoteOn, 0, 60, 0);
+        let mut note_off = message(MessageType::NoteOff, 0, 60, 10);
+        assert!(fixed.apply(&mut note_on) && fixed.apply(&mut silent_on) && fixed.apply(&mut note_off));
+        assert_eq!((note_on.velocity, silent_on.velocity, note_off.velocity), (90, 0, 10));
+    }
+
+    #[test]
+    fn dedup_slots() {
+        let mut dedup = Dedup::new();
+        let mut pass = |msg_type: MessageType, channel: u8, key: u8, velocity: u8| {
+            dedup.apply(&mut message(msg_type, channel, key, velocity))
+        };
+
+        assert!(pass(MessageType::NoteOn, 0, 60, 100));
+        assert!(!pass(MessageType::NoteOn, 0, 60, 100));
+        assert!(pass(MessageType::NoteOn, 0, 61, 100));
+        // Note offs share the slot of the note on, whatever their velocity.
+        assert!(pass(MessageType::NoteOff, 0, 60, 64));
+        assert!(!pass(MessageType::NoteOn, 0, 60, 0));
+        assert!(pass(MessageType::NoteOn, 1, 60, 0));
+
+        assert!(pass(MessageType::CC, 0, 7, 64));
+        assert!(!pass(MessageType::CC, 0, 7, 64));
+        assert!(pass(MessageType::CC, 0, 8, 64));
+
+        // Unkeyed messages get one slot per channel.
+        assert!(pass(MessageType::PC, 0, 5, 0));
+        assert!(!pass(MessageType::PC, 0, 5, 0));
+        assert!(pass(MessageType::PC, 0, 6, 0));
+
+        assert!(pass(MessageType::Clock, 0, 0, 0));
+        assert!(pass(MessageType::Clock, 0, 0, 0));
+
+        dedup.reset();
+        assert!(dedup.apply(&mut message(MessageType::CC, 0, 7, 64)));
+    }
+
+    #[test]
+    fn note_to_cc() {
+        let mut note_on = message(MessageType::NoteOn, 2, 60, 100);
+        let mut note_off = message(MessageType::NoteOff, 2, 60, 64);
+        let mut aftertouch = message(MessageType::NoteVelocity, 2, 60, 30);
+        let mut program = message(MessageType::PC, 2, 5, 0);
+
+        assert!(NoteToCc.apply(&mut note_on));
+        assert_eq!(note_on, message(MessageType::CC, 2, 60, 100));
+        assert!(NoteToCc.apply(&mut note_off));
+        assert_eq!(note_off, message(MessageType::CC, 2, 60, 0));
+        assert!(!NoteToCc.apply(&mut aftertouch));
+        assert!(NoteToCc.apply(&mut program));
+        assert_eq!(program, message(MessageType::PC, 2, 5, 0));
+    }
+
+    #[test]
+    fn chain_stops_at_drop() {
+        let counter = |count: &Arc<AtomicUsize>| {
+            let count = count.clone();
+            move |_: &mut MidiMessage| {
+                count.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        };
+        let (before, after) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+        let mut chain = Chain::new();
+        chain.with_stage(counter(&before))
+            .with_stage(Transpose(12))
+            .with_stage(KeyRange(0..=63))
+            .with_stage(counter(&after));
+
+        let mut low = message(MessageType::NoteOn, 0, 40, 100);
+        assert!(chain.apply(&mut low));
+        assert_eq!(low.key, 52);
+
+        assert!(!chain.apply(&mut message(MessageType::NoteOn, 0, 60, 100)));
+        assert_eq!((before.load(Ordering::SeqCst), after.load(Ordering::SeqCst)), (2, 1));
+    }
+}
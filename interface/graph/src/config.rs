@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use physical::PortSelector;
+use router::Router;
+use router::transform::{Chain, Transpose, ChannelRemap, VelocityCurve, CcRenumber, NoteToCc, KeyRange, Dedup};
+
+use crate::GraphError;
+
+/// The whole routing description, as read from TOML.
+///
+/// Every input feeds its own router, so edges can start at inputs or routers,
+/// and end at routers or outputs.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphConfig {
+    pub inputs: BTreeMap<String, PortConfig>,
+    pub outputs: BTreeMap<String, PortConfig>,
+    pub routers: BTreeMap<String, RouterConfig>,
+    pub edges: Vec<EdgeConfig>
+}
+
+impl GraphConfig {
+    pub fn from_toml(text: &str) -> Result<GraphConfig, GraphError> {
+        toml::from_str(text).map_err(GraphError::Parse)
+    }
+}
+
+/// `{ contains = "LPX MIDI" }`, `{ regex = "^Launchpad" }`, `{ index = 1 }`,
+/// `{ exact = "..." }`, or `"virtual"` to create a port under the node's name.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortConfig {
+    Index(usize),
+    Exact(String),
+    Contains(String),
+    Regex(String),
+    Virtual
+}
+
+impl PortConfig {
+    /// None for virtual ports.
+    pub fn selector(&self) -> Result<Option<PortSelector>, GraphError> {
+        Ok(Some(match self {
+            PortConfig::Index(index) => PortSelector::Index(*index),
+            PortConfig::Exact(name) => PortSelector::Exact(name.clone()),
+            PortConfig::Contains(name) => PortSelector::Contains(name.clone()),
+            PortConfig::Regex(pattern) => PortSelector::regex(pattern)
+                .map_err(|err| GraphError::Invalid(format!("port regex {}: {}", pattern, err)))?,
+            PortConfig::Virtual => return Ok(None)
+        }))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterConfig {
+    pub function: FunctionConfig
+}
+
+/// `{ kind = "on_off" }`, `{ kind = "note_ranges", ranges = [{ low = 0, high = 63, output = "left" }] }`...
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FunctionConfig {
+    MirrorAll,
+    SplitByDevice,
+    OnOff,
+    NoteRanges { ranges: Vec<RangeConfig> },
+    VelocityLayers { layers: Vec<RangeConfig> }
+}
+
+impl FunctionConfig {
+    pub fn router(&self) -> Router {
+        match self {
+            FunctionConfig::MirrorAll => Router::mirror_all(),
+            FunctionConfig::SplitByDevice => Router::split_by_device(),
+            FunctionConfig::OnOff => Router::on_off(),
+            FunctionConfig::NoteRanges { ranges } => Router::note_ranges(RangeConfig::ranges(ranges)),
+            FunctionConfig::VelocityLayers { layers } => Router::velocity_layers(RangeConfig::ranges(layers))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RangeConfig {
+    pub low: u8,
+    pub high: u8,
+    pub output: String
+}
+
+impl RangeConfig {
+    fn ranges(ranges: &[RangeConfig]) -> Vec<(std::ops::RangeInclusive<u8>, String)> {
+        ranges.iter()
+            .map(|range| (range.low..=range.high, range.output.clone()))
+            .collect()
+    }
+}
+
+/// Connects `from` to `to`.
+///
+/// `output` is the name the routing function of `from` targets, and defaults to `to`.
+/// Transforms run in order, on messages leaving `from` through this edge.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EdgeConfig {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub transforms: Vec<TransformConfig>
+}
+
+impl EdgeConfig {
+    pub fn output_name(&self) -> &str {
+        self.output.as_ref().unwrap_or(&self.to)
+    }
+}
+
+/// `{ kind = "transpose", semitones = 12 }`, `{ kind = "note_to_cc" }`...
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransformConfig {
+    Transpose { semitones: i8 },
+    /// Pairs of `[from, to]` channels.
+    ChannelRemap { map: Vec<(u8, u8)> },
+    ChannelAll { channel: u8 },
+    VelocityLinear { min: u8, max: u8 },
+    VelocityExponential { exponent: f32 },
+    VelocityFixed { velocity: u8 },
+    /// Pairs of `[from, to]` controllers.
+    CcRenumber { map: Vec<(u8, u8)> },
+    NoteToCc,
+    KeyRange { low: u8, high: u8 },
+    Dedup
+}
+
+impl TransformConfig {
+    pub fn chain(transforms: &[TransformConfig]) -> Chain {
+        let mut chain = Chain::new();
+
+        for transform in transforms {
+            match transform {
+                TransformConfig::Transpose { semitones } => chain.with_stage(Transpose(*semitones)),
+                TransformConfig::ChannelRemap { map } => {
+                    let mut remap = ChannelRemap::new();
+                    for (from, to) in map {
+                        remap.with_channel(*from, *to);
+                    }
+                    chain.with_stage(remap)
+                },
+                TransformConfig::ChannelAll { channel } => chain.with_stage(ChannelRemap::all_to(*channel)),
+                TransformConfig::VelocityLinear { min, max } => chain.with_stage(VelocityCurve::Linear { min: *min, max: *max }),
+                TransformConfig::VelocityExponential { exponent } => chain.with_stage(VelocityCurve::Exponential(*exponent)),
+                TransformConfig::VelocityFixed { velocity } => chain.with_stage(VelocityCurve::Fixed(*velocity)),
+                TransformConfig::CcRenumber { map } => {
+                    let mut renumber = CcRenumber::new();
+                    for (from, to) in map {
+                        renumber.with_cc(*from, *to);
+                    }
+                    chain.with_stage(renumber)
+                },
+                TransformConfig::NoteToCc => chain.with_stage(NoteToCc),
+                TransformConfig::KeyRange { low, high } => chain.with_stage(KeyRange(*low..=*high)),
+                TransformConfig::Dedup => chain.with_stage(Dedup::new())
+            };
+        }
+
+        chain
+    }
+}
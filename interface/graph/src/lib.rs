@@ -0,0 +1,268 @@
+//! Builds live devices and routers from a declarative routing description.
+//!
+//! ```toml
+//! [inputs]
+//! launchpad = { contains = "LPX MIDI" }
+//!
+//! [outputs]
+//! synth = "virtual"
+//!
+//! [routers.split]
+//! function = { kind = "note_ranges", ranges = [{ low = 0, high = 63, output = "low" }] }
+//!
+//! [[edges]]
+//! from = "launchpad"
+//! to = "split"
+//!
+//! [[edges]]
+//! from = "split"
+//! output = "low"
+//! to = "synth"
+//! transforms = [{ kind = "transpose", semitones = -12 }]
+//! ```
+
+mod config;
+
+use std::collections::{BTreeMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crossbeam_channel::unbounded;
+
+use midichan_core::Error;
+use midichan_core::device::{HasInput, HasOutput, RoutingDevice};
+use midichan_core::message::MidiMessage;
+use physical::{InputDevice, OutputDevice, PortSelector};
+use router::Router;
+
+pub use config::{GraphConfig, PortConfig, RouterConfig, FunctionConfig, RangeConfig, EdgeConfig, TransformConfig};
+
+#[derive(Debug)]
+pub enum GraphError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+    UnknownNode(String),
+    Duplicate(String),
+    Device(Error)
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::Io(err) => write!(f, "failed to read config: {}", err),
+            GraphError::Parse(err) => write!(f, "failed to parse config: {}", err),
+            GraphError::Invalid(desc) => write!(f, "invalid config: {}", desc),
+            GraphError::UnknownNode(name) => write!(f, "unknown node: {}", name),
+            GraphError::Duplicate(name) => write!(f, "defined more than once: {}", name),
+            GraphError::Device(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl error::Error for GraphError {}
+
+impl From<Error> for GraphError {
+    fn from(err: Error) -> GraphError {
+        GraphError::Device(err)
+    }
+}
+
+/// Devices and routers built from a `GraphConfig`, running until dropped.
+pub struct Graph {
+    // Routers go first, so they stop before the devices they read from.
+    routers: BTreeMap<String, Router>,
+    inputs: BTreeMap<String, InputDevice>,
+    output: OutputDevice
+}
+
+fn open_port<B, V>(name: &str, port: &PortConfig, open_by: B, open_virtual: V) -> Result<(), GraphError>
+    where B: Fn(String, &PortSelector) -> Result<(), Error>, V: Fn(String) -> Result<(), Error> {
+    match port.selector()? {
+        Some(selector) => open_by(name.to_string(), &selector)?,
+        None => open_virtual(name.to_string())?
+    }
+
+    Ok(())
+}
+
+/// Depth first, erroring out on the first router reached again through `path`.
+fn visit<'a>(node: &'a str, next: &BTreeMap<&'a str, Vec<&'a str>>, path: &mut Vec<&'a str>, done: &mut HashSet<&'a str>) -> Result<(), GraphError> {
+    if done.contains(node) {
+        return Ok(());
+    }
+
+    if let Some(start) = path.iter().position(|name| *name == node) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(node);
+        return Err(GraphError::Invalid(format!("routers feed back into each other: {}", cycle.join(" -> "))));
+    }
+
+    path.push(node);
+    for to in next.get(node).into_iter().flatten() {
+        visit(to, next, path, done)?;
+    }
+    path.pop();
+
+    done.insert(node);
+    Ok(())
+}
+
+/// Messages would go around a cycle of routers forever.
+fn check_cycles(config: &GraphConfig) -> Result<(), GraphError> {
+    let mut next: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for edge in config.edges.iter().filter(|edge| config.routers.contains_key(&edge.to)) {
+        next.entry(&edge.from).or_default().push(&edge.to);
+    }
+
+    let mut done = HashSet::new();
+    for node in next.keys() {
+        visit(node, &next, &mut Vec::new(), &mut done)?;
+    }
+    Ok(())
+}
+
+impl Graph {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Graph, GraphError> {
+        let text = fs::read_to_string(path).map_err(GraphError::Io)?;
+        Graph::build(&GraphConfig::from_toml(&text)?)
+    }
+
+    pub fn build(config: &GraphConfig) -> Result<Graph, GraphError> {
+        let mut names = HashSet::new();
+        for name in config.inputs.keys().chain(config.outputs.keys()).chain(config.routers.keys()) {
+            if !names.insert(name) {
+                return Err(GraphError::Duplicate(name.clone()));
+            }
+        }
+        check_cycles(config)?;
+
+        let output = OutputDevice::new();
+        for (name, port) in config.outputs.iter() {
+            open_port(name, port, |name, selector| output.open_port_by(name, selector), |name| output.open_virtual_port(name))?;
+        }
+
+        // Inputs get a router of their own, so they can feed more than one edge.
+        let mut inputs = BTreeMap::new();
+        let mut routers = BTreeMap::new();
+        for (name, port) in config.inputs.iter() {
+            let input = InputDevice::new();
+            open_port(name, port, |name, selector| input.open_port_by(name, selector), |name| input.open_virtual_port(name))?;
+
+            let router = Router::mirror_all();
+            router.add_input(name.clone(), input.midi_in())?;
+
+            inputs.insert(name.clone(), input);
+            routers.insert(name.clone(), router);
+        }
+
+        for (name, router) in config.routers.iter() {
+            routers.insert(name.clone(), router.function.router());
+        }
+
+        let mut edges = HashSet::new();
+        for edge in config.edges.iter() {
+            let output_name = edge.output_name().to_string();
+            if !edges.insert((&edge.from, output_name.clone())) {
+                return Err(GraphError::Duplicate(format!("{} -> {}", edge.from, output_name)));
+            }
+
+            let source = routers.get(&edge.from)
+                .ok_or_else(|| GraphError::UnknownNode(edge.from.clone()))?;
+            let mut chain = TransformConfig::chain(&edge.transforms);
+
+            if config.outputs.contains_key(&edge.to) {
+                source.add_output(output_name.clone(), output.midi_out())?;
+
+                // The output device picks the port by the device name.
+                let port_name = edge.to.clone();
+                chain.with_stage(move |msg: &mut MidiMessage| {
+                    msg.device = port_name.clone();
+                    true
+                });
+            } else if config.routers.contains_key(&edge.to) {
+                let (send, recv) = unbounded();
+                source.add_output(output_name.clone(), send)?;
+                routers[&edge.to].add_input(format!("{}:{}", edge.from, output_name), recv)?;
+            } else {
+                return Err(GraphError::UnknownNode(edge.to.clone()));
+            }
+
+            source.set_transform(output_name, Box::new(chain))?;
+        }
+
+        Ok(Graph{routers, inputs, output})
+    }
+
+    /// Inputs are also reachable here, under their own name.
+    pub fn router(&self, name: &str) -> Option<&Router> {
+        self.routers.get(name)
+    }
+
+    pub fn input(&self, name: &str) -> Option<&InputDevice> {
+        self.inputs.get(name)
+    }
+
+    pub fn output(&self) -> &OutputDevice {
+        &self.output
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTERS: &str = r#"
+        [routers.a]
+        function = { kind = "mirror_all" }
+
+        [routers.b]
+        function = { kind = "mirror_all" }
+
+        [routers.c]
+        function = { kind = "mirror_all" }
+    "#;
+
+    fn config(edges: &str) -> GraphConfig {
+        GraphConfig::from_toml(&format!("{}\n{}", ROUTERS, edges)).unwrap()
+    }
+
+    #[test]
+    fn router_cycles() {
+        let chain = config(r#"
+            [[edges]]
+            from = "a"
+            to = "b"
+
+            [[edges]]
+            from = "b"
+            to = "c"
+
+            [[edges]]
+            from = "a"
+            to = "c"
+        "#);
+        assert!(check_cycles(&chain).is_ok());
+
+        let cycle = config(r#"
+            [[edges]]
+            from = "a"
+            to = "b"
+
+            [[edges]]
+            from = "b"
+            to = "a"
+        "#);
+        assert!(matches!(check_cycles(&cycle), Err(GraphError::Invalid(_))));
+
+        let to_itself = config(r#"
+            [[edges]]
+            from = "c"
+            to = "c"
+        "#);
+        assert!(matches!(check_cycles(&to_itself), Err(GraphError::Invalid(_))));
+    }
+}
@@ -0,0 +1,30 @@
+use std::env;
+use std::io;
+
+use graph::Graph;
+
+/// Runs the routing graph described in the TOML file given as the only argument,
+/// until Enter is pressed.
+pub fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            println!("usage: midichan-graph <config.toml>");
+            return;
+        }
+    };
+
+    let graph = match Graph::load(&path) {
+        Ok(graph) => graph,
+        Err(err) => {
+            println!("loading {} errored: {}", path, err);
+            return;
+        }
+    };
+
+    println!("routing {}, press Enter to stop", path);
+    io::stdin().read_line(&mut String::new())
+        .unwrap_or_else(|x| { println!("reading stdin errored: {}", x); 0 });
+
+    drop(graph);
+}
@@ -0,0 +1,243 @@
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
+use std::thread;
+
+use hashbrown::HashMap;
+use crossbeam_channel::{unbounded, Sender, Receiver};
+
+use midichan_core::Error;
+use midichan_core::message::{MidiMessage, MidiParser, ParseError};
+use midichan_core::device::{HasInput, HasOutput, MidiDevice};
+
+struct Recorded {
+    messages: Mutex<Vec<MidiMessage>>,
+    added: Condvar
+}
+
+/// In-memory device for tests.
+///
+/// Records everything sent to `midi_out`, and hands out injected messages on `midi_in`.
+/// Both channels are unbounded, so neither side ever blocks.
+pub struct MockDevice {
+    ports: Vec<String>,
+    open: Mutex<HashMap<String, usize>>,
+
+    input: Sender<MidiMessage>,
+    midi_in: Receiver<MidiMessage>,
+    midi_out: Sender<MidiMessage>,
+
+    recorded: Arc<Recorded>,
+    /// The input, while looping back.
+    loopback: Arc<Mutex<Option<Sender<MidiMessage>>>>
+}
+
+impl MockDevice {
+    pub fn new() -> MockDevice {
+        MockDevice::with_ports(vec!["Mock".to_string()])
+    }
+
+    /// Ports returned by `query_all`, and accepted by `open_port`.
+    pub fn with_ports(ports: Vec<String>) -> MockDevice {
+        let (input, midi_in) = unbounded();
+        let (midi_out, thread_out) = unbounded();
+
+        let recorded = Arc::new(Recorded{messages: Mutex::new(Vec::new()), added: Condvar::new()});
+        let loopback = Arc::new(Mutex::new(None));
+
+        let thread_recorded = recorded.clone();
+        let thread_loopback = loopback.clone();
+
+        thread::spawn(move || {
+            recorder_thread(thread_out, thread_recorded, thread_loopback);
+        });
+
+        MockDevice{
+            ports,
+            open: Mutex::new(HashMap::new()),
+            input, midi_in, midi_out,
+            recorded, loopback
+        }
+    }
+
+    /// Echoes sent messages back on the input.
+    pub fn set_loopback(&self, loopback: bool) {
+        *self.loopback.lock().unwrap() = match loopback {
+            true => Some(self.input.clone()),
+            false => None
+        };
+    }
+
+    pub fn inject(&self, msg: MidiMessage) {
+        self.input.send(msg).ok();
+    }
+
+    pub fn inject_at(&self, timestamp: u64, mut msg: MidiMessage) {
+        msg.timestamp = timestamp;
+        self.inject(msg);
+    }
+
+    /// Parses raw bytes as if they came from the port `name`.
+    ///
+    /// Everything that parses gets injected, the first error is returned.
+    pub fn inject_raw(&self, name: &str, timestamp: u64, bytes: &[u8]) -> Result<(), ParseError> {
+        let (messages, errors) = MidiParser::new(name).parse(timestamp, bytes);
+
+        for msg in messages {
+            self.inject(msg);
+        }
+
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+
+    /// Everything sent so far.
+    pub fn sent(&self) -> Vec<MidiMessage> {
+        self.recorded.messages.lock().unwrap().clone()
+    }
+
+    /// Everything sent so far, clearing the record.
+    pub fn take_sent(&self) -> Vec<MidiMessage> {
+        self.recorded.messages.lock().unwrap().split_off(0)
+    }
+
+    /// Waits until at least `count` messages were recorded, or the timeout elapses.
+    pub fn wait_for_sent(&self, count: usize, timeout: Duration) -> Vec<MidiMessage> {
+        let deadline = Instant::now() + timeout;
+        let mut messages = self.recorded.messages.lock().unwrap();
+
+        while messages.len() < count {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            messages = self.recorded.added.wait_timeout(messages, deadline - now).unwrap().0;
+        }
+
+        messages.clone()
+    }
+}
+
+impl Default for MockDevice {
+    fn default() -> MockDevice {
+        MockDevice::new()
+    }
+}
+
+/// Lets `midi_in` disconnect, even while something still holds `midi_out`.
+impl Drop for MockDevice {
+    fn drop(&mut self) {
+        self.loopback.lock().unwrap().take();
+    }
+}
+
+impl HasInput for MockDevice {
+    fn midi_in(&self) -> Receiver<MidiMessage> {
+        self.midi_in.clone()
+    }
+}
+
+impl HasOutput for MockDevice {
+    fn midi_out(&self) -> Sender<MidiMessage> {
+        self.midi_out.clone()
+    }
+}
+
+impl MidiDevice for MockDevice {
+    fn open_port(&self, name: String, port_id: usize) -> Result<(), Error> {
+        let mut open = self.open.lock().unwrap();
+
+        if open.contains_key(&name) {
+            return Err(Error::AlreadyOpen(name));
+        }
+        if port_id >= self.ports.len() {
+            return Err(Error::PortNotFound(format!("{} (#{})", name, port_id)));
+        }
+
+        open.insert(name, port_id);
+        Ok(())
+    }
+
+    fn close_port(&self, name: String) -> Result<(), Error> {
+        self.open.lock().unwrap().remove(&name);
+        Ok(())
+    }
+
+    fn query(&self, name: String) -> Result<bool, Error> {
+        Ok(self.open.lock().unwrap().contains_key(&name))
+    }
+
+    fn query_all(&self) -> Result<Vec<String>, Error> {
+        Ok(self.ports.clone())
+    }
+}
+
+
+fn recorder_thread(midi_out: Receiver<MidiMessage>, recorded: Arc<Recorded>, loopback: Arc<Mutex<Option<Sender<MidiMessage>>>>) {
+    for msg in midi_out.iter() {
+        if let Some(input) = loopback.lock().unwrap().as_ref() {
+            input.send(msg.clone()).ok();
+        }
+
+        recorded.messages.lock().unwrap().push(msg);
+        recorded.added.notify_all();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossbeam_channel::RecvTimeoutError;
+    use launchpad_x::{LaunchpadX, BYTE_HEADER};
+
+    const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    fn sysex(body: &[u8]) -> Vec<u8> {
+        BYTE_HEADER.iter().chain(body).chain(&[0xF7]).copied().collect()
+    }
+
+    #[test]
+    fn launchpad_x_init() {
+        let midi = MockDevice::new();
+        let daw = MockDevice::new();
+
+        let launchpad = LaunchpadX::new(midi.midi_in(), midi.midi_out(), daw.midi_in(), daw.midi_out()).unwrap();
+        let sent: Vec<_> = midi.wait_for_sent(2, TIMEOUT).into_iter().map(|msg| msg.sysex).collect();
+
+        // DAW mode on, then out of programmer mode.
+        assert_eq!(sent, vec![Some(sysex(&[0x10, 0x01])), Some(sysex(&[0x0E, 0x00]))]);
+        assert!(daw.sent().is_empty());
+        drop(launchpad);
+    }
+
+    #[test]
+    fn loopback() {
+        let mock = MockDevice::new();
+        let midi_in = mock.midi_in();
+        let mut msg = MidiMessage::new("Mock");
+        msg.with_key(60);
+
+        mock.set_loopback(true);
+        mock.midi_out().send(msg.clone()).unwrap();
+        assert_eq!(midi_in.recv_timeout(TIMEOUT).unwrap().key, 60);
+
+        mock.set_loopback(false);
+        mock.midi_out().send(msg).unwrap();
+        mock.wait_for_sent(2, TIMEOUT);
+        assert!(midi_in.try_recv().is_err());
+    }
+
+    #[test]
+    fn input_disconnects_when_dropped() {
+        let mock = MockDevice::new();
+        let midi_in = mock.midi_in();
+        let _midi_out = mock.midi_out();
+
+        drop(mock);
+        assert_eq!(midi_in.recv_timeout(TIMEOUT).unwrap_err(), RecvTimeoutError::Disconnected);
+    }
+}
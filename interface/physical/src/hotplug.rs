@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+
+use midichan_core::message::DeviceEvent;
+
+/// How often the port list is checked for unplugged and replugged devices.
+pub const RESCAN: Duration = Duration::from_secs(2);
+
+/// An opened port, remembered by name so it can be reopened after a replug.
+pub struct OpenPort<T> {
+    pub port_name: String,
+    pub connection: Option<T>,
+    /// Virtual ports are owned by us, and never rescanned.
+    pub is_virtual: bool
+}
+
+/// Where the port of an opened device is in the port list.
+///
+/// The ALSA `client:port` address at the end of the name changes every time a device is
+/// plugged in, so it is ignored once the exact name is gone, skipping ports `taken` by
+/// other devices. Identical devices differ only by their address.
+pub fn find_port(ports: &[String], port_name: &str, taken: &[String]) -> Option<usize> {
+    ports.iter().position(|x| x == port_name)
+        .or_else(|| ports.iter().position(|x| !taken.contains(x) && same_port(x, port_name)))
+}
+
+/// Compares port names, ignoring the trailing ALSA `client:port` address.
+fn same_port(first: &str, second: &str) -> bool {
+    strip_address(first) == strip_address(second)
+}
+
+fn strip_address(name: &str) -> &str {
+    match name.rfind(' ') {
+        Some(pos) if is_address(&name[pos + 1..]) => &name[..pos],
+        _ => name
+    }
+}
+
+fn is_address(word: &str) -> bool {
+    let mut parts = word.split(':');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(client), Some(port), None) => {
+            !client.is_empty() && !port.is_empty()
+                && client.chars().chain(port.chars()).all(|x| x.is_ascii_digit())
+        },
+        _ => false
+    }
+}
+
+/// Sends `PortAdded` and `PortRemoved` events, dropping them if nobody is listening.
+pub fn diff_ports(events: &Sender<DeviceEvent>, known: &[String], current: &[String]) {
+    for port in current.iter().filter(|x| !known.contains(x)) {
+        events.try_send(DeviceEvent::PortAdded(port.clone())).ok();
+    }
+
+    for port in known.iter().filter(|x| !current.contains(x)) {
+        events.try_send(DeviceEvent::PortRemoved(port.clone())).ok();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(ports: &[&str]) -> Vec<String> {
+        ports.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn identical_devices() {
+        let taken = names(&["LPX MIDI 20:0", "LPX MIDI 24:0"]);
+
+        let ports = names(&["Midi Through 14:0", "LPX MIDI 20:0", "LPX MIDI 24:0"]);
+        assert_eq!(find_port(&ports, "LPX MIDI 20:0", &taken), Some(1));
+        assert_eq!(find_port(&ports, "LPX MIDI 24:0", &taken), Some(2));
+
+        // The first one got unplugged.
+        let ports = names(&["Midi Through 14:0", "LPX MIDI 24:0"]);
+        assert_eq!(find_port(&ports, "LPX MIDI 20:0", &taken), None);
+        assert_eq!(find_port(&ports, "LPX MIDI 24:0", &taken), Some(1));
+
+        // And came back under a new address.
+        let ports = names(&["Midi Through 14:0", "LPX MIDI 24:0", "LPX MIDI 28:0"]);
+        assert_eq!(find_port(&ports, "LPX MIDI 20:0", &taken), Some(2));
+    }
+
+    #[test]
+    fn addresses() {
+        assert!(same_port("LPX MIDI 20:0", "LPX MIDI 28:1"));
+        assert!(same_port("LPX MIDI", "LPX MIDI"));
+        assert!(!same_port("LPX MIDI 20:0", "LPX DAW 20:0"));
+        assert!(!same_port("LPX MIDI 20:", "LPX MIDI 28:"));
+    }
+}
@@ -5,24 +5,32 @@ extern crate midichan_core;
 
 #[macro_use]
 mod macros;
+mod selector;
+mod hotplug;
+
+pub use selector::PortSelector;
 
 use std::time::Duration;
-use std::error::Error;
+use std::error;
 use std::thread;
 
 use hashbrown::HashMap;
-use crossbeam_channel::{bounded, Sender, Receiver};
-use midir::{MidiInput, MidiOutput, Ignore};
+use crossbeam_channel::{bounded, tick, Sender, Receiver, RecvTimeoutError};
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection, Ignore};
 
-use midichan_core::message::{DeviceRequest, DeviceResponse, MidiMessage};
+use midichan_core::message::{DeviceRequest, DeviceResponse, DeviceEvent, MidiMessage, MidiParser};
+use midichan_core::Error;
 use midichan_core::device::{Controllable, HasInput, HasOutput, MidiDevice};
 
+use hotplug::{OpenPort, RESCAN, find_port, diff_ports};
+
 const TIMEOUT: Duration = Duration::from_secs(1);
 
 
 pub struct InputDevice {
     control_request: Sender<DeviceRequest>,
     control_response: Receiver<DeviceResponse>,
+    events: Receiver<DeviceEvent>,
     midi_in: Receiver<MidiMessage>
 }
 
@@ -30,13 +38,38 @@ impl InputDevice {
     pub fn new() -> InputDevice {
         let (exported_send, thread_recv) = bounded(1);
         let (thread_send, exported_recv) = bounded(2);
+        let (thread_events, exported_events) = bounded(16);
         let (thread_midi, exported_midi) = bounded(128);
 
         thread::spawn(move || {
-            input_wrapper(thread_midi, thread_recv, thread_send);
+            input_wrapper(thread_midi, thread_events, thread_recv, thread_send);
         });
 
-        InputDevice{control_request: exported_send, control_response: exported_recv, midi_in: exported_midi}
+        InputDevice{control_request: exported_send, control_response: exported_recv, events: exported_events, midi_in: exported_midi}
+    }
+
+    /// Hotplug events, and input dropped because `midi_in` wasn't read. Dropped when the channel is full.
+    pub fn events(&self) -> Receiver<DeviceEvent> {
+        self.events.clone()
+    }
+
+    pub fn open_port_by(&self, name: String, selector: &PortSelector) -> Result<(), Error> {
+        selector::open_matching(self, name, selector)
+    }
+
+    /// Creates a port named `name` that other software can connect to. Unix only.
+    pub fn open_virtual_port(&self, name: String) -> Result<(), Error> {
+        error_on_full!(self.control_response, "input device");
+        send_or_err!(self.control_request, DeviceRequest::OpenVirtualIn(name), "input device");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(DeviceResponse::Ok) => Ok(()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("input device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("input device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("input device".to_string()))
+        }
     }
 }
 
@@ -57,33 +90,35 @@ impl Controllable<DeviceRequest, DeviceResponse> for InputDevice {
 }
 
 impl MidiDevice for InputDevice {
-    fn open_port(&self, name: String, port_id: usize) -> Result<(), String> {
+    fn open_port(&self, name: String, port_id: usize) -> Result<(), Error> {
         error_on_full!(self.control_response, "input device");
         send_or_err!(self.control_request, DeviceRequest::OpenIn(name, port_id), "input device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Ok) => Ok(()),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("input device desync".to_string()),
-            Err(_) => Err("input device timed out".to_string())
+            Ok(_) => Err(Error::Desync("input device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("input device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("input device".to_string()))
         }
     }
 
-    fn close_port(&self, name: String) -> Result<(), String> {
+    fn close_port(&self, name: String) -> Result<(), Error> {
         error_on_full!(self.control_response, "input device");
         send_or_err!(self.control_request, DeviceRequest::CloseIn(name), "input device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Ok) => Ok(()),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("input device desync".to_string()),
-            Err(_) => Err("input device timed out".to_string())
+            Ok(_) => Err(Error::Desync("input device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("input device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("input device".to_string()))
         }
     }
 
-    fn query(&self, name: String) -> Result<bool, String> {
+    fn query(&self, name: String) -> Result<bool, Error> {
         error_on_full!(self.control_response, "input device");
         send_or_err!(self.control_request, DeviceRequest::QueryDevice(name), "input device");
         // self.control_request.send(
@@ -91,23 +126,25 @@ impl MidiDevice for InputDevice {
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Device(_, status))  => Ok(status),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("input device desync".to_string()),
-            Err(_) => Err("input device timed out".to_string())
+            Ok(_) => Err(Error::Desync("input device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("input device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("input device".to_string()))
         }
     }
 
-    fn query_all(&self) -> Result<Vec<String>, String> {
+    fn query_all(&self) -> Result<Vec<String>, Error> {
         error_on_full!(self.control_response, "input device");
         send_or_err!(self.control_request, DeviceRequest::QueryList, "input device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::List(list)) => Ok(list),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("input device desync".to_string()),
-            Err(_) => Err("input device timed out".to_string())
+            Ok(_) => Err(Error::Desync("input device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("input device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("input device".to_string()))
         }
     }
 }
@@ -121,8 +158,8 @@ impl Drop for InputDevice {
 }
 
 
-fn input_wrapper(midi_out: Sender<MidiMessage>, control_request: Receiver<DeviceRequest>, control_response: Sender<DeviceResponse>) {
-    match input_thread(midi_out, &control_request, &control_response) {
+fn input_wrapper(midi_out: Sender<MidiMessage>, events: Sender<DeviceEvent>, control_request: Receiver<DeviceRequest>, control_response: Sender<DeviceResponse>) {
+    match input_thread(midi_out, &events, &control_request, &control_response) {
         Ok(()) => {},
 
         Err(err) => { //control_response.send( 
@@ -132,8 +169,79 @@ fn input_wrapper(midi_out: Sender<MidiMessage>, control_request: Receiver<Device
     };
 }
 
-fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<DeviceRequest>, control_response: &Sender<DeviceResponse>) -> Result<(), Box<dyn Error>> {
-    let mut map = HashMap::new();
+/// Lists the ports with the client kept for scanning, creating it first if needed.
+fn input_ports(scanner: &mut Option<MidiInput>) -> Result<Vec<String>, Error> {
+    let in_bridge = match scanner.take() {
+        Some(in_bridge) => in_bridge,
+        None => input_bridge()?
+    };
+
+    let ports = (0..in_bridge.port_count())
+        .map( |x| in_bridge.port_name(x)
+                .unwrap_or("Error".to_string()))
+        .collect();
+
+    *scanner = Some(in_bridge);
+    Ok(ports)
+}
+
+fn input_callback(name: &str, midi_out: &Sender<MidiMessage>, events: &Sender<DeviceEvent>) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+    let clone_out = midi_out.clone();
+    let clone_events = events.clone();
+    let name = name.to_string();
+    let mut parser = MidiParser::new(&name);
+
+    move |timestamp: u64, message: &[u8], _: &mut _| {
+        // Malformed input is dropped, the parser resyncs on the next status byte.
+        let (messages, _) = parser.parse(timestamp, message);
+
+        for msg in messages {
+            // Not a control response, that would answer the next request instead.
+            if clone_out.send_timeout(msg, TIMEOUT).is_err() {
+                clone_events.try_send(DeviceEvent::Dropped(name.clone())).ok();
+            }
+        }
+    }
+}
+
+fn input_bridge() -> Result<MidiInput, Error> {
+    let mut in_bridge = MidiInput::new("launchpad-rs")
+        .map_err(|err| Error::Backend(format!("input handler: {}", err)))?;
+    in_bridge.ignore(Ignore::All);
+
+    Ok(in_bridge)
+}
+
+fn connect_input(name: &str, port: usize, midi_out: &Sender<MidiMessage>, events: &Sender<DeviceEvent>) -> Result<MidiInputConnection<()>, Error> {
+    input_bridge()?
+        .connect(port, "launchpad-rs", input_callback(name, midi_out, events), ())
+        .map_err(|err| Error::Backend(format!("input handler: failed to add device: {}", err)))
+}
+
+#[cfg(unix)]
+fn connect_virtual_input(name: &str, midi_out: &Sender<MidiMessage>, events: &Sender<DeviceEvent>) -> Result<MidiInputConnection<()>, Error> {
+    use midir::os::unix::VirtualInput;
+
+    input_bridge()?
+        .create_virtual(name, input_callback(name, midi_out, events), ())
+        .map_err(|err| Error::Backend(format!("input handler: failed to create virtual port: {}", err)))
+}
+
+#[cfg(not(unix))]
+fn connect_virtual_input(_name: &str, _midi_out: &Sender<MidiMessage>, _events: &Sender<DeviceEvent>) -> Result<MidiInputConnection<()>, Error> {
+    Err(Error::Unsupported("input handler: virtual ports".to_string()))
+}
+
+fn input_thread(midi_out: Sender<MidiMessage>, events: &Sender<DeviceEvent>, control_request: &Receiver<DeviceRequest>, control_response: &Sender<DeviceResponse>) -> Result<(), Box<dyn error::Error>> {
+    let mut map: HashMap<String, OpenPort<MidiInputConnection<()>>> = HashMap::new();
+
+    // Connecting takes a client of its own, this one is only used for listing ports.
+    let mut scanner = None;
+    let mut known_ports = input_ports(&mut scanner).unwrap_or_else(|err| {
+        events.try_send(DeviceEvent::ScanFailed(err)).ok();
+        Vec::new()
+    });
+    let rescan = tick(RESCAN);
 
     loop {
         select!{
@@ -141,27 +249,27 @@ fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<Device
                 DeviceRequest::OpenIn(name, port) => {
                     if map.contains_key(&name) {
                         control_response.send(
-                            DeviceResponse::Error("input handler: device already added".to_string()))?;
+                            DeviceResponse::Error(Error::AlreadyOpen(name)))?;
                         continue;
                     }
 
-                    let clone_out = midi_out.clone();
-                    let clone_response = control_response.clone();
-                    let clone_name = name.clone();
-                    
-                    let mut in_bridge = MidiInput::new("launchpad-rs")?;
-                    in_bridge.ignore(Ignore::All);
-
-                    match in_bridge.connect(port, "launchpad-rs", 
-                        move |timestamp: u64, message: &[u8], _: &mut _| {
-                            clone_out.send_timeout(MidiMessage::from_raw(&clone_name, timestamp, message), TIMEOUT)
-                                .or_else(|_| 
-                                    clone_response.send(DeviceResponse::Error("input handler: channel timeout".to_string()) ) )
-                                .expect("Could not send Error message");
-                        }, ())
-                    {
+                    let port_name = match input_ports(&mut scanner).map(|ports| ports.get(port).cloned()) {
+                        Ok(Some(port_name)) => port_name,
+                        Err(err) => {
+                            control_response.send(
+                                DeviceResponse::Error(err))?;
+                            continue;
+                        },
+                        Ok(None) => {
+                            control_response.send(
+                                DeviceResponse::Error(Error::PortNotFound(format!("{} (#{})", name, port))))?;
+                            continue;
+                        }
+                    };
+
+                    match connect_input(&name, port, &midi_out, events) {
                         Ok(midi_in) => {
-                            map.insert(name, midi_in);
+                            map.insert(name, OpenPort{port_name, connection: Some(midi_in), is_virtual: false});
 
                             control_response.send(
                                 DeviceResponse::Ok)?;
@@ -169,14 +277,36 @@ fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<Device
 
                         Err(err) => {
                             control_response.send(
-                                DeviceResponse::Error( format!("input handler: failed to add device: {}", err.to_string()) ))?
+                                DeviceResponse::Error(err))?
                         }
                     }
 
                 },
 
+                DeviceRequest::OpenVirtualIn(name) => {
+                    if map.contains_key(&name) {
+                        control_response.send(
+                            DeviceResponse::Error(Error::AlreadyOpen(name)))?;
+                        continue;
+                    }
+
+                    match connect_virtual_input(&name, &midi_out, events) {
+                        Ok(midi_in) => {
+                            map.insert(name.clone(), OpenPort{port_name: name, connection: Some(midi_in), is_virtual: true});
+
+                            control_response.send(
+                                DeviceResponse::Ok)?;
+                        },
+
+                        Err(err) => {
+                            control_response.send(
+                                DeviceResponse::Error(err))?
+                        }
+                    }
+                },
+
                 DeviceRequest::CloseIn(name) => {
-                    map.remove(&name).map(|x| x.close());
+                    map.remove(&name).and_then(|x| x.connection).map(|x| x.close());
 
                     control_response.send(
                         DeviceResponse::Ok)?;
@@ -188,23 +318,16 @@ fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<Device
                 },
 
                 DeviceRequest::QueryList => {
-                    let mut in_bridge = MidiInput::new("launchpad-rs")?;
-                    in_bridge.ignore(Ignore::All);
-
-                    let response = (0..in_bridge.port_count())
-                        .map( |x| in_bridge.port_name(x)
-                                .unwrap_or("Error".to_string()))
-                        .collect();
-
                     control_response.send(
-                        DeviceResponse::List(response))?;
+                        match input_ports(&mut scanner) {
+                            Ok(ports) => DeviceResponse::List(ports),
+                            Err(err) => DeviceResponse::Error(err)
+                        })?;
                 },
 
                 DeviceRequest::Shutdown => {
-                    let keys: Vec<String> = map.keys().cloned().collect();
-
-                    for key in keys {
-                        map.remove(&key).map(|x| x.close());
+                    for (_, port) in map.drain() {
+                        port.connection.map(|x| x.close());
                     }
 
                     control_response.send(
@@ -215,8 +338,48 @@ fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<Device
 
                 _ => {
                     control_response.send(
-                        DeviceResponse::Error("output handler: unknown command".to_string()))?;
+                        DeviceResponse::Error(Error::Unsupported("input handler".to_string())))?;
                 }
+            },
+
+            recv(rescan) -> _ => {
+                let ports = match input_ports(&mut scanner) {
+                    Ok(ports) => ports,
+                    Err(err) => {
+                        events.try_send(DeviceEvent::ScanFailed(err)).ok();
+                        continue;
+                    }
+                };
+                diff_ports(events, &known_ports, &ports);
+
+                let mut taken: Vec<String> = map.values()
+                    .filter(|x| !x.is_virtual)
+                    .map(|x| x.port_name.clone())
+                    .collect();
+
+                for (name, open) in map.iter_mut().filter(|(_, x)| !x.is_virtual) {
+                    let index = find_port(&ports, &open.port_name, &taken);
+
+                    match (index, open.connection.is_some()) {
+                        (None, true) => {
+                            open.connection.take().map(|x| x.close());
+                            events.try_send(DeviceEvent::Disconnected(name.clone())).ok();
+                        },
+
+                        (Some(port), false) => {
+                            if let Ok(midi_in) = connect_input(name, port, &midi_out, events) {
+                                open.port_name = ports[port].clone();
+                                open.connection = Some(midi_in);
+                                taken.push(open.port_name.clone());
+                                events.try_send(DeviceEvent::Reconnected(name.clone())).ok();
+                            }
+                        },
+
+                        _ => ()
+                    }
+                }
+
+                known_ports = ports;
             }
         }
     }
@@ -227,6 +390,7 @@ fn input_thread(midi_out: Sender<MidiMessage>, control_request: &Receiver<Device
 pub struct OutputDevice {
     control_request: Sender<DeviceRequest>,
     control_response: Receiver<DeviceResponse>,
+    events: Receiver<DeviceEvent>,
     midi_out: Sender<MidiMessage>
 }
 
@@ -234,13 +398,38 @@ impl OutputDevice {
     pub fn new() -> OutputDevice {
         let (exported_send, thread_recv) = bounded(0);
         let (thread_send, exported_recv) = bounded(2);
+        let (thread_events, exported_events) = bounded(16);
         let (exported_midi, thread_midi) = bounded(128);
 
         thread::spawn(move || {
-            output_wrapper(thread_midi, thread_recv, thread_send);
+            output_wrapper(thread_midi, thread_events, thread_recv, thread_send);
         });
 
-        OutputDevice{control_request: exported_send, control_response: exported_recv, midi_out: exported_midi}
+        OutputDevice{control_request: exported_send, control_response: exported_recv, events: exported_events, midi_out: exported_midi}
+    }
+
+    /// Hotplug events, dropped when the channel is full.
+    pub fn events(&self) -> Receiver<DeviceEvent> {
+        self.events.clone()
+    }
+
+    pub fn open_port_by(&self, name: String, selector: &PortSelector) -> Result<(), Error> {
+        selector::open_matching(self, name, selector)
+    }
+
+    /// Creates a port named `name` that other software can connect to. Unix only.
+    pub fn open_virtual_port(&self, name: String) -> Result<(), Error> {
+        error_on_full!(self.control_response, "output device");
+        send_or_err!(self.control_request, DeviceRequest::OpenVirtualOut(name), "output device");
+
+        match self.control_response.recv_timeout(TIMEOUT) {
+            Ok(DeviceResponse::Ok) => Ok(()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
+
+            Ok(_) => Err(Error::Desync("output device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("output device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("output device".to_string()))
+        }
     }
 }
 
@@ -261,55 +450,59 @@ impl Controllable<DeviceRequest, DeviceResponse> for OutputDevice {
 }
 
 impl MidiDevice for OutputDevice {
-    fn open_port(&self, name: String, port_id: usize) -> Result<(), String> {
+    fn open_port(&self, name: String, port_id: usize) -> Result<(), Error> {
         error_on_full!(self.control_response, "output device");
         send_or_err!(self.control_request, DeviceRequest::OpenOut(name, port_id), "output device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Ok) => Ok(()),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("output device desync".to_string()),
-            Err(_) => Err("output device timed out".to_string())
+            Ok(_) => Err(Error::Desync("output device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("output device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("output device".to_string()))
         }
     }
 
-    fn close_port(&self, name: String) -> Result<(), String> {
+    fn close_port(&self, name: String) -> Result<(), Error> {
         error_on_full!(self.control_response, "output device");
         send_or_err!(self.control_request, DeviceRequest::CloseOut(name), "output device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Ok) => Ok(()),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("output device desync".to_string()),
-            Err(_) => Err("output device timed out".to_string())
+            Ok(_) => Err(Error::Desync("output device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("output device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("output device".to_string()))
         }
     }
 
-    fn query(&self, name: String) -> Result<bool, String> {
+    fn query(&self, name: String) -> Result<bool, Error> {
         error_on_full!(self.control_response, "output device");
         send_or_err!(self.control_request, DeviceRequest::QueryDevice(name), "output device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::Device(_, status))  => Ok(status),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("output device desync".to_string()),
-            Err(_) => Err("output device timed out".to_string())
+            Ok(_) => Err(Error::Desync("output device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("output device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("output device".to_string()))
         }
     }
 
-    fn query_all(&self) -> Result<Vec<String>, String> {
+    fn query_all(&self) -> Result<Vec<String>, Error> {
         error_on_full!(self.control_response, "output device");
         send_or_err!(self.control_request, DeviceRequest::QueryList, "output device");
 
         match self.control_response.recv_timeout(TIMEOUT) {
             Ok(DeviceResponse::List(list)) => Ok(list),
-            Ok(DeviceResponse::Error(err)) => Err(err.to_string()),
+            Ok(DeviceResponse::Error(err)) => Err(err),
 
-            Ok(_) => Err("output device desync".to_string()),
-            Err(_) => Err("output device timed out".to_string())
+            Ok(_) => Err(Error::Desync("output device".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout("output device".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed("output device".to_string()))
         }
     }
 }
@@ -323,8 +516,8 @@ impl Drop for OutputDevice {
 }
 
 
-fn output_wrapper(midi_in: Receiver<MidiMessage>, control_request: Receiver<DeviceRequest>, control_response: Sender<DeviceResponse>) {
-    match output_thread(midi_in, &control_request, &control_response) {
+fn output_wrapper(midi_in: Receiver<MidiMessage>, events: Sender<DeviceEvent>, control_request: Receiver<DeviceRequest>, control_response: Sender<DeviceResponse>) {
+    match output_thread(midi_in, &events, &control_request, &control_response) {
         Ok(()) => {},
         Err(err) => { //control_response.send( 
             // DeviceResponse::Error(format!("output handler died: {}", err.to_string()) )); }
@@ -333,8 +526,57 @@ fn output_wrapper(midi_in: Receiver<MidiMessage>, control_request: Receiver<Devi
     };
 }
 
-fn output_thread(midi_in: Receiver<MidiMessage>, control_request: &Receiver<DeviceRequest>, control_response: &Sender<DeviceResponse>) -> Result<(), Box<dyn Error>> {
-    let mut map = HashMap::new();
+/// Lists the ports with the client kept for scanning, creating it first if needed.
+fn output_ports(scanner: &mut Option<MidiOutput>) -> Result<Vec<String>, Error> {
+    let out_bridge = match scanner.take() {
+        Some(out_bridge) => out_bridge,
+        None => output_bridge()?
+    };
+
+    let ports = (0..out_bridge.port_count())
+        .map( |x| out_bridge.port_name(x)
+                .unwrap_or("Error".to_string()))
+        .collect();
+
+    *scanner = Some(out_bridge);
+    Ok(ports)
+}
+
+fn output_bridge() -> Result<MidiOutput, Error> {
+    MidiOutput::new("launchpad-rs")
+        .map_err(|err| Error::Backend(format!("output handler: {}", err)))
+}
+
+fn connect_output(port: usize) -> Result<MidiOutputConnection, Error> {
+    output_bridge()?
+        .connect(port, "launchpad-rs")
+        .map_err(|err| Error::Backend(format!("output handler: failed to add device: {}", err)))
+}
+
+#[cfg(unix)]
+fn connect_virtual_output(name: &str) -> Result<MidiOutputConnection, Error> {
+    use midir::os::unix::VirtualOutput;
+
+    output_bridge()?
+        .create_virtual(name)
+        .map_err(|err| Error::Backend(format!("output handler: failed to create virtual port: {}", err)))
+}
+
+#[cfg(not(unix))]
+fn connect_virtual_output(_name: &str) -> Result<MidiOutputConnection, Error> {
+    Err(Error::Unsupported("output handler: virtual ports".to_string()))
+}
+
+fn output_thread(midi_in: Receiver<MidiMessage>, events: &Sender<DeviceEvent>, control_request: &Receiver<DeviceRequest>, control_response: &Sender<DeviceResponse>) -> Result<(), Box<dyn error::Error>> {
+    let mut map: HashMap<String, OpenPort<MidiOutputConnection>> = HashMap::new();
+
+    // Connecting takes a client of its own, this one is only used for listing ports.
+    let mut scanner = None;
+    let mut known_ports = output_ports(&mut scanner).unwrap_or_else(|err| {
+        events.try_send(DeviceEvent::ScanFailed(err)).ok();
+        Vec::new()
+    });
+    let rescan = tick(RESCAN);
 
     loop {
         select!{
@@ -342,15 +584,27 @@ fn output_thread(midi_in: Receiver<MidiMessage>, control_request: &Receiver<Devi
                 DeviceRequest::OpenOut(name, port) => {
                     if map.contains_key(&name) {
                         control_response.send(
-                            DeviceResponse::Error("output handler: device already added".to_string()))?;
+                            DeviceResponse::Error(Error::AlreadyOpen(name)))?;
                         continue;
                     }
 
-                    let out_bridge = MidiOutput::new("launchpad-rs")?;
+                    let port_name = match output_ports(&mut scanner).map(|ports| ports.get(port).cloned()) {
+                        Ok(Some(port_name)) => port_name,
+                        Err(err) => {
+                            control_response.send(
+                                DeviceResponse::Error(err))?;
+                            continue;
+                        },
+                        Ok(None) => {
+                            control_response.send(
+                                DeviceResponse::Error(Error::PortNotFound(format!("{} (#{})", name, port))))?;
+                            continue;
+                        }
+                    };
 
-                    match out_bridge.connect(port, "launchpad-rs") {
+                    match connect_output(port) {
                         Ok(midi_out) => {
-                            map.insert(name, midi_out);
+                            map.insert(name, OpenPort{port_name, connection: Some(midi_out), is_virtual: false});
 
                             control_response.send(
                                 DeviceResponse::Ok)?;
@@ -358,14 +612,36 @@ fn output_thread(midi_in: Receiver<MidiMessage>, control_request: &Receiver<Devi
 
                         Err(err) => {
                             control_response.send(
-                                DeviceResponse::Error( format!("output handler: failed to add device: {}", err.to_string()) ))?
+                                DeviceResponse::Error(err))?
                         }
                     }
 
                 },
 
+                DeviceRequest::OpenVirtualOut(name) => {
+                    if map.contains_key(&name) {
+                        control_response.send(
+                            DeviceResponse::Error(Error::AlreadyOpen(name)))?;
+                        continue;
+                    }
+
+                    match connect_virtual_output(&name) {
+                        Ok(midi_out) => {
+                            map.insert(name.clone(), OpenPort{port_name: name, connection: Some(midi_out), is_virtual: true});
+
+                            control_response.send(
+                                DeviceResponse::Ok)?;
+                        }
+
+                        Err(err) => {
+                            control_response.send(
+                                DeviceResponse::Error(err))?
+                        }
+                    }
+                },
+
                 DeviceRequest::CloseOut(name) => {
-                    map.remove(&name).map(|x| x.close());
+                    map.remove(&name).and_then(|x| x.connection).map(|x| x.close());
 
                     control_response.send(
                         DeviceResponse::Ok)?;
@@ -377,22 +653,16 @@ fn output_thread(midi_in: Receiver<MidiMessage>, control_request: &Receiver<Devi
                 },
 
                 DeviceRequest::QueryList => {
-                    let out_bridge = MidiOutput::new("launchpad-rs")?;
-
-                    let response = (0..out_bridge.port_count())
-                        .map( |x| out_bridge.port_name(x)
-                                .unwrap_or("Error".to_string()))
-                        .collect();
-
                     control_response.send(
-                        DeviceResponse::List(response))?;
+                        match output_ports(&mut scanner) {
+                            Ok(ports) => DeviceResponse::List(ports),
+                            Err(err) => DeviceResponse::Error(err)
+                        })?;
                 },
                 
                 DeviceRequest::Shutdown => {
-                    let keys: Vec<String> = map.keys().cloned().collect();
-
-                    for key in keys {
-                        map.remove(&key).map(|x| x.close());
+                    for (_, port) in map.drain() {
+                        port.connection.map(|x| x.close());
                     }
 
                     control_response.send(
@@ -403,22 +673,70 @@ fn output_thread(midi_in: Receiver<MidiMessage>, control_request: &Receiver<Devi
 
                 _ => {
                     control_response.send(
-                        DeviceResponse::Error("output handler: unknown command".to_string()))?;
+                        DeviceResponse::Error(Error::Unsupported("output handler".to_string())))?;
                 }
             },
             
             recv(midi_in) -> msg => {
                 let midi_msg = msg?;
-                if let Some(midi_out) = map.get_mut(&midi_msg.device) {
+                let raw = midi_msg.to_raw();
+                if raw.is_empty() {
+                    continue;
+                }
 
-                    if let Err(err) = midi_out.send(&midi_msg.to_raw()) {
-                        control_response.send(
-                            DeviceResponse::Error( format!("output handler: failed to add device: {}", err.to_string()) ))?
-                    } else {
-                        //println!("Sent! ({:?})", midi_msg.to_raw());
+                // Messages sent while the device is unplugged are dropped.
+                if let Some(open) = map.get_mut(&midi_msg.device) {
+                    let failed = match open.connection.as_mut() {
+                        Some(midi_out) => midi_out.send(&raw).is_err(),
+                        None => false
+                    };
+
+                    if failed {
+                        open.connection.take().map(|x| x.close());
+                        events.try_send(DeviceEvent::Disconnected(midi_msg.device.clone())).ok();
                     }
                 }
             },
+
+            recv(rescan) -> _ => {
+                let ports = match output_ports(&mut scanner) {
+                    Ok(ports) => ports,
+                    Err(err) => {
+                        events.try_send(DeviceEvent::ScanFailed(err)).ok();
+                        continue;
+                    }
+                };
+                diff_ports(events, &known_ports, &ports);
+
+                let mut taken: Vec<String> = map.values()
+                    .filter(|x| !x.is_virtual)
+                    .map(|x| x.port_name.clone())
+                    .collect();
+
+                for (name, open) in map.iter_mut().filter(|(_, x)| !x.is_virtual) {
+                    let index = find_port(&ports, &open.port_name, &taken);
+
+                    match (index, open.connection.is_some()) {
+                        (None, true) => {
+                            open.connection.take().map(|x| x.close());
+                            events.try_send(DeviceEvent::Disconnected(name.clone())).ok();
+                        },
+
+                        (Some(port), false) => {
+                            if let Ok(midi_out) = connect_output(port) {
+                                open.port_name = ports[port].clone();
+                                open.connection = Some(midi_out);
+                                taken.push(open.port_name.clone());
+                                events.try_send(DeviceEvent::Reconnected(name.clone())).ok();
+                            }
+                        },
+
+                        _ => ()
+                    }
+                }
+
+                known_ports = ports;
+            }
         }
     }
-}
\ No newline at end of file
+}
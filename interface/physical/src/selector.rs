@@ -0,0 +1,87 @@
+use std::fmt;
+
+use regex::Regex;
+
+use midichan_core::Error;
+use midichan_core::device::MidiDevice;
+
+/// Picks a port out of the list returned by `MidiDevice::query_all`.
+#[derive(Clone, Debug)]
+pub enum PortSelector {
+    Index(usize),
+    Exact(String),
+    Contains(String),
+    Regex(Regex)
+}
+
+impl PortSelector {
+    pub fn regex(pattern: &str) -> Result<PortSelector, regex::Error> {
+        Ok(PortSelector::Regex(Regex::new(pattern)?))
+    }
+
+    pub fn matches(&self, index: usize, port_name: &str) -> bool {
+        match self {
+            PortSelector::Index(id) => *id == index,
+            PortSelector::Exact(name) => port_name == name,
+            PortSelector::Contains(name) => port_name.contains(name.as_str()),
+            PortSelector::Regex(regex) => regex.is_match(port_name)
+        }
+    }
+
+    /// Index of the first matching port.
+    pub fn resolve(&self, ports: &[String]) -> Result<usize, Error> {
+        ports.iter()
+            .enumerate()
+            .position(|(index, port_name)| self.matches(index, port_name))
+            .ok_or_else(|| Error::PortNotFound(
+                format!("{} (available: {})", self, ports.join(", "))))
+    }
+}
+
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortSelector::Index(id) => write!(f, "#{}", id),
+            PortSelector::Exact(name) => write!(f, "\"{}\"", name),
+            PortSelector::Contains(name) => write!(f, "*{}*", name),
+            PortSelector::Regex(regex) => write!(f, "/{}/", regex)
+        }
+    }
+}
+
+/// Opens the first port matching the selector, named `name` from then on.
+pub fn open_matching<T: MidiDevice>(device: &T, name: String, selector: &PortSelector) -> Result<(), Error> {
+    let port_id = selector.resolve(&device.query_all()?)?;
+    device.open_port(name, port_id)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports() -> Vec<String> {
+        vec!["Midi Through 14:0", "LPX DAW 20:0", "LPX MIDI 20:1"].into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn resolve() {
+        let ports = ports();
+
+        assert_eq!(PortSelector::Index(2).resolve(&ports), Ok(2));
+        assert_eq!(PortSelector::Exact("LPX DAW 20:0".to_string()).resolve(&ports), Ok(1));
+        assert_eq!(PortSelector::Contains("LPX".to_string()).resolve(&ports), Ok(1));
+        assert_eq!(PortSelector::regex("^LPX MIDI").unwrap().resolve(&ports), Ok(2));
+    }
+
+    #[test]
+    fn no_match() {
+        let ports = ports();
+
+        assert!(PortSelector::Index(3).resolve(&ports).is_err());
+        assert!(PortSelector::Exact("LPX DAW".to_string()).resolve(&ports).is_err());
+        assert_eq!(
+            PortSelector::Contains("Launchpad".to_string()).resolve(&ports),
+            Err(Error::PortNotFound("*Launchpad* (available: Midi Through 14:0, LPX DAW 20:0, LPX MIDI 20:1)".to_string())));
+    }
+}
@@ -1,5 +1,7 @@
+#[cfg(not(feature = "emulator"))]
 use midichan_core::device::{MidiDevice, HasInput, HasOutput, Application};
-use physical::{InputDevice, OutputDevice};
+#[cfg(not(feature = "emulator"))]
+use physical::{InputDevice, OutputDevice, PortSelector};
 
 
 /// Uses the launchpad Mini.
@@ -11,16 +13,15 @@ pub fn main() {
     use simple::{DisplayPressed, DrawOneColor, Rainbow};
     use chain_reaction::ChainReaction;
 
-    const IN_PORT:  usize = 2;
-    const OUT_PORT: usize = 2;
+    const PORT: &str = "Launchpad Mini";
 
 
     let in_device = InputDevice::new();
     let out_device = OutputDevice::new();
 
-    in_device.open_port("Launchpad".to_string(), IN_PORT)
+    in_device.open_port_by("Launchpad".to_string(), &PortSelector::Contains(PORT.to_string()))
         .unwrap_or_else(|x| println!("opening errored: {}", x));
-    out_device.open_port("Launchpad".to_string(), OUT_PORT)
+    out_device.open_port_by("Launchpad".to_string(), &PortSelector::Contains(PORT.to_string()))
         .unwrap_or_else(|x| println!("opening errored: {}", x));
 
     let launchpad = Launchpad::new(in_device.midi_in(), out_device.midi_out());
@@ -50,32 +51,54 @@ pub fn main() {
         .unwrap_or_else(|x| println!("closing errored: {}", x));
 }
 
-/// Uses the Launchpad X.
+/// Notes played by the apps go to `notes`.
 #[cfg(feature = "x")]
-pub fn main() {
-    use launchpad_x::{LaunchpadX, Color};
-
+fn select_x(launchpad: launchpad_x::LaunchpadX, notes: crossbeam_channel::Sender<midichan_core::message::MidiMessage>) -> select_x::Select {
     use select_x::Select;
     use simple_x::{DisplayPressed, DrawOneColor, Rainbow};
     use chain_reaction_x::ChainReaction;
+    use sequencer_x::Sequencer;
+
+    let dp = DisplayPressed::new(launchpad.clone());
+    let doc = DrawOneColor::new(launchpad.clone());
+    let rb = Rainbow::new(launchpad.clone());
+    let cr = ChainReaction::new(launchpad.clone());
+    let seq = Sequencer::new(launchpad.clone(), notes);
+    // let chain = ChainReaction::new(launchpad.clone());
+    
+    let mut select = Select::new(launchpad);
+    select.add("Display pressed".to_string(), Box::new(dp));
+    select.add("Draw one color".to_string(), Box::new(doc));
+    select.add("Rainbow".to_string(), Box::new(rb));
+    select.add("Chain Reaction".to_string(), Box::new(cr));
+    select.add("Sequencer".to_string(), Box::new(seq));
+    //select.add(Box::new(chain));
+
+    select
+}
+
+/// Uses the Launchpad X.
+#[cfg(all(feature = "x", not(feature = "emulator")))]
+pub fn main() {
+    use launchpad_x::LaunchpadX;
 
-    const DAW_IN:  usize = 7;
-    const DAW_OUT:  usize = 7;
-    const MIDI_IN:  usize = 8;
-    const MIDI_OUT:  usize = 8;
+    const DAW_PORT:  &str = "LPX DAW";
+    const MIDI_PORT: &str = "LPX MIDI";
 
     let daw_in = InputDevice::new();
     let daw_out = OutputDevice::new();
     let midi_in = InputDevice::new();
     let midi_out = OutputDevice::new();
 
-    daw_in.open_port("Launchpad DAW".to_string(), DAW_IN)
+    daw_in.open_port_by("Launchpad DAW".to_string(), &PortSelector::Contains(DAW_PORT.to_string()))
+        .unwrap_or_else(|x| println!("opening errored: {}", x));
+    daw_out.open_port_by("Launchpad DAW".to_string(), &PortSelector::Contains(DAW_PORT.to_string()))
         .unwrap_or_else(|x| println!("opening errored: {}", x));
-    daw_out.open_port("Launchpad DAW".to_string(), DAW_OUT)
+    midi_in.open_port_by("Launchpad MIDI".to_string(), &PortSelector::Contains(MIDI_PORT.to_string()))
         .unwrap_or_else(|x| println!("opening errored: {}", x));
-    midi_in.open_port("Launchpad MIDI".to_string(), MIDI_IN)
+    midi_out.open_port_by("Launchpad MIDI".to_string(), &PortSelector::Contains(MIDI_PORT.to_string()))
         .unwrap_or_else(|x| println!("opening errored: {}", x));
-    midi_out.open_port("Launchpad MIDI".to_string(), MIDI_OUT)
+    midi_out.open_virtual_port("Sequencer".to_string())
         .unwrap_or_else(|x| println!("opening errored: {}", x));
 
     let launchpad = LaunchpadX::new(
@@ -83,22 +106,7 @@ pub fn main() {
         daw_in.midi_in(), daw_out.midi_out(),
     ).expect("Failed to open Launchpad X in DAW mode");
         
-    let mut select = {
-        let dp = DisplayPressed::new(launchpad.clone());
-        let doc = DrawOneColor::new(launchpad.clone());
-        let rb = Rainbow::new(launchpad.clone());
-        let cr = ChainReaction::new(launchpad.clone());
-        // let chain = ChainReaction::new(launchpad.clone());
-        
-        let mut select = Select::new(launchpad);
-        select.add("Display pressed".to_string(), Box::new(dp));
-        select.add("Draw one color".to_string(), Box::new(doc));
-        select.add("Rainbow".to_string(), Box::new(rb));
-        select.add("Chain Reaction".to_string(), Box::new(cr));
-        //select.add(Box::new(chain));
-
-        select
-    };
+    let mut select = select_x(launchpad, midi_out.midi_out());
 
     select.run()
         .unwrap_or_else(|x| println!("running select errored: {}", x));
@@ -113,4 +121,34 @@ pub fn main() {
         .unwrap_or_else(|x| println!("closing errored: {}", x));
     midi_out.close_port("Launchpad MIDI".to_string())
         .unwrap_or_else(|x| println!("closing errored: {}", x));
+    midi_out.close_port("Sequencer".to_string())
+        .unwrap_or_else(|x| println!("closing errored: {}", x));
+}
+
+/// Uses the Launchpad X emulator, in the terminal.
+#[cfg(all(feature = "x", feature = "emulator"))]
+pub fn main() {
+    use midichan_core::device::Application;
+    use launchpad_x::LaunchpadX;
+    use launchpad_x_emulator::Emulator;
+
+    let emulator = Emulator::new();
+
+    let launchpad = LaunchpadX::new(
+        emulator.input(), emulator.output(),
+        emulator.daw_input(), emulator.daw_output(),
+    ).expect("Failed to open emulated Launchpad X");
+
+    // The emulator has no sound, notes are dropped.
+    let (notes, dropped_notes) = crossbeam_channel::bounded(0);
+    std::thread::spawn(move || dropped_notes.iter().for_each(drop));
+
+    let mut select = select_x(launchpad, notes);
+    let result = select.run();
+
+    drop(select);
+    // Restores the terminal before printing.
+    drop(emulator);
+
+    result.unwrap_or_else(|x| println!("running select errored: {}", x));
 }
@@ -0,0 +1,390 @@
+//! Recording messages off a channel, and playing them back with the same timing.
+
+use std::collections::HashMap;
+use std::error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{select, never, unbounded, Sender, Receiver, RecvTimeoutError};
+
+use crate::Error;
+use crate::message::MidiMessage;
+use crate::smf::{Smf, SmfFormat, SmfTrack};
+
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+const MIN_SPEED: f64 = 0.01;
+const MAX_SPEED: f64 = 100.0;
+
+/// Recorded messages, timestamps in microseconds from the first one.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    /// Sorted by timestamp.
+    pub messages: Vec<MidiMessage>,
+    /// Microseconds until the end of the recording, at least the last timestamp.
+    pub length: u64
+}
+
+impl Capture {
+    pub fn new(mut messages: Vec<MidiMessage>) -> Capture {
+        messages.sort_by_key(|msg| msg.timestamp);
+        let length = messages.last().map(|msg| msg.timestamp).unwrap_or(0);
+
+        Capture{messages, length}
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Every track merged, messages keep the track names as device.
+    pub fn from_smf(smf: &Smf) -> Capture {
+        Capture::new(smf.tracks.iter()
+            .flat_map(|track| track.messages.iter().cloned())
+            .collect())
+    }
+
+    /// One track per device, at the default tempo.
+    pub fn to_smf(&self) -> Smf {
+        let mut smf = Smf::new(SmfFormat::MultiTrack);
+        let mut tracks: Vec<SmfTrack> = Vec::new();
+
+        for msg in self.messages.iter() {
+            match tracks.iter_mut().find(|track| track.name == msg.device) {
+                Some(track) => track.messages.push(msg.clone()),
+                None => tracks.push(SmfTrack::new(&msg.device, vec![msg.clone()]))
+            }
+        }
+
+        for track in tracks {
+            smf.with_track(track);
+        }
+        smf
+    }
+}
+
+
+pub enum RecorderRequest {
+    Snapshot,
+    Stop
+}
+
+/// Records everything coming from a channel, optionally passing it on.
+///
+/// Uses the timestamps of the messages when they have one, lined up per device with the time
+/// the first message of the device arrived, otherwise the time the message arrived.
+pub struct Recorder {
+    control_request: Sender<RecorderRequest>,
+    control_response: Receiver<Capture>
+}
+
+impl Recorder {
+    pub fn new(input: Receiver<MidiMessage>, through: Option<Sender<MidiMessage>>) -> Recorder {
+        let (exported_send, thread_recv) = unbounded();
+        let (thread_send, exported_recv) = unbounded();
+
+        thread::spawn(move || {
+            recorder_wrapper(input, through, thread_recv, thread_send);
+        });
+
+        Recorder{control_request: exported_send, control_response: exported_recv}
+    }
+
+    fn request(&self, request: RecorderRequest) -> Result<Capture, Error> {
+        self.control_request.send(request)
+            .map_err(|_| Error::ChannelClosed("recorder".to_string()))?;
+
+        self.control_response.recv_timeout(TIMEOUT)
+            .map_err(|err| match err {
+                RecvTimeoutError::Timeout => Error::Timeout("recorder".to_string()),
+                RecvTimeoutError::Disconnected => Error::ChannelClosed("recorder".to_string())
+            })
+    }
+
+    /// Everything recorded so far, while recording goes on.
+    pub fn snapshot(&self) -> Result<Capture, Error> {
+        self.request(RecorderRequest::Snapshot)
+    }
+
+    /// Stops recording, and stops passing messages on.
+    pub fn stop(self) -> Result<Capture, Error> {
+        self.request(RecorderRequest::Stop)
+    }
+}
+
+
+fn recorder_wrapper(input: Receiver<MidiMessage>, through: Option<Sender<MidiMessage>>, control_request: Receiver<RecorderRequest>, control_response: Sender<Capture>) {
+    match recorder_thread(input, &through, &control_request, &control_response) {
+        Ok(()) => {},
+        Err(err) => {
+            panic!("recorder died: {}", err);
+        }
+    };
+}
+
+fn recorder_thread(mut input: Receiver<MidiMessage>, through: &Option<Sender<MidiMessage>>, control_request: &Receiver<RecorderRequest>, control_response: &Sender<Capture>) -> Result<(), Box<dyn error::Error>> {
+    let origin = Instant::now();
+    // Device timestamp to recorder time, per device.
+    let mut offsets: HashMap<String, i64> = HashMap::new();
+
+    let mut start = None;
+    let mut messages: Vec<MidiMessage> = Vec::new();
+
+    let snapshot = |messages: &Vec<MidiMessage>, start: Option<u64>| {
+        let now = origin.elapsed().as_micros() as u64;
+        let mut capture = Capture::new(messages.clone());
+        capture.length = capture.length.max(now.saturating_sub(start.unwrap_or(now)));
+        capture
+    };
+
+    loop {
+        select! {
+            recv(input) -> msg => match msg {
+                Ok(mut msg) => {
+                    let now = origin.elapsed().as_micros() as i64;
+                    let time = match msg.timestamp {
+                        0 => now,
+                        timestamp => {
+                            let offset = offsets.entry(msg.device.clone()).or_insert(now - timestamp as i64);
+                            timestamp as i64 + *offset
+                        }
+                    }.max(0) as u64;
+
+                    if let Some(through) = through {
+                        through.send(msg.clone())?;
+                    }
+
+                    let start = *start.get_or_insert(time);
+                    msg.timestamp = time.saturating_sub(start);
+                    messages.push(msg);
+                },
+
+                // Keep the capture around until it's asked for.
+                Err(_) => input = never()
+            },
+
+            recv(control_request) -> msg => match msg {
+                Ok(RecorderRequest::Snapshot) => {
+                    control_response.send(
+                        snapshot(&messages, start))?;
+                },
+
+                Ok(RecorderRequest::Stop) => {
+                    control_response.send(
+                        snapshot(&messages, start))?;
+                    return Ok(());
+                },
+
+                Err(_) => return Ok(())
+            }
+        }
+    }
+}
+
+
+pub enum PlayerRequest {
+    Play,
+    Pause,
+    Rewind,
+    SetSpeed(f64),
+    SetLooping(bool),
+
+    Shutdown
+}
+
+/// Plays a capture into a channel, with the original timing scaled by the speed.
+///
+/// Starts paused. Messages are sent with timestamps in microseconds since the player was created.
+pub struct Player {
+    control_request: Sender<PlayerRequest>,
+    finished: Receiver<()>
+}
+
+impl Player {
+    pub fn new(capture: Capture, output: Sender<MidiMessage>) -> Player {
+        let (exported_send, thread_recv) = unbounded();
+        let (finished_send, finished_recv) = unbounded();
+
+        thread::spawn(move || {
+            player_wrapper(capture, output, thread_recv, finished_send);
+        });
+
+        Player{control_request: exported_send, finished: finished_recv}
+    }
+
+    fn request(&self, request: PlayerRequest) -> Result<(), Error> {
+        self.control_request.send(request)
+            .map_err(|_| Error::ChannelClosed("player".to_string()))
+    }
+
+    pub fn play(&self) -> Result<(), Error> {
+        self.request(PlayerRequest::Play)
+    }
+
+    pub fn pause(&self) -> Result<(), Error> {
+        self.request(PlayerRequest::Pause)
+    }
+
+    /// Back to the start of the capture, without pausing.
+    pub fn rewind(&self) -> Result<(), Error> {
+        self.request(PlayerRequest::Rewind)
+    }
+
+    /// 2.0 plays twice as fast. Clamped to 0.01..=100, NaN and infinite speeds are ignored.
+    pub fn set_speed(&self, speed: f64) -> Result<(), Error> {
+        self.request(PlayerRequest::SetSpeed(speed))
+    }
+
+    pub fn set_looping(&self, is_looping: bool) -> Result<(), Error> {
+        self.request(PlayerRequest::SetLooping(is_looping))
+    }
+
+    /// Blocks until playback reaches the end, once for every time it did.
+    ///
+    /// Never returns while looping.
+    pub fn wait(&self) -> Result<(), Error> {
+        self.finished.recv()
+            .map_err(|_| Error::ChannelClosed("player".to_string()))
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.control_request.send(
+            PlayerRequest::Shutdown ).ok();
+    }
+}
+
+
+fn player_wrapper(capture: Capture, output: Sender<MidiMessage>, control_request: Receiver<PlayerRequest>, finished: Sender<()>) {
+    match player_thread(&capture, &output, &control_request, &finished) {
+        Ok(()) => {},
+        Err(err) => {
+            panic!("player died: {}", err);
+        }
+    };
+}
+
+fn player_thread(capture: &Capture, output: &Sender<MidiMessage>, control_request: &Receiver<PlayerRequest>, finished: &Sender<()>) -> Result<(), Box<dyn error::Error>> {
+    let origin = Instant::now();
+    let length = capture.length.max(capture.messages.last().map(|msg| msg.timestamp).unwrap_or(0));
+
+    let mut is_playing = false;
+    let mut is_looping = false;
+    let mut speed = 1.0;
+
+    // Capture position at the anchor, playback moves on from there.
+    let mut anchor = Instant::now();
+    let mut anchor_position = 0;
+    let mut next = 0;
+
+    let position = |anchor: Instant, anchor_position: u64, speed: f64| {
+        anchor_position + (anchor.elapsed().as_micros() as f64 * speed) as u64
+    };
+
+    loop {
+        let request = if is_playing {
+            let target = capture.messages.get(next).map(|msg| msg.timestamp).unwrap_or(length);
+            let wait = target.saturating_sub(position(anchor, anchor_position, speed));
+            control_request.recv_timeout(Duration::from_micros((wait as f64 / speed) as u64))
+        } else {
+            control_request.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match request {
+            Ok(PlayerRequest::Play) => {
+                if !is_playing {
+                    is_playing = true;
+                    anchor = Instant::now();
+                }
+            },
+
+            Ok(PlayerRequest::Pause) => {
+                if is_playing {
+                    is_playing = false;
+                    anchor_position = position(anchor, anchor_position, speed);
+                }
+            },
+
+            Ok(PlayerRequest::Rewind) => {
+                anchor = Instant::now();
+                anchor_position = 0;
+                next = 0;
+            },
+
+            // NaN can't be clamped, and would keep the timeout at zero.
+            Ok(PlayerRequest::SetSpeed(new_speed)) if new_speed.is_finite() => {
+                if is_playing {
+                    anchor_position = position(anchor, anchor_position, speed);
+                    anchor = Instant::now();
+                }
+                speed = new_speed.clamp(MIN_SPEED, MAX_SPEED);
+            },
+
+            Ok(PlayerRequest::SetSpeed(_)) => (),
+
+            Ok(PlayerRequest::SetLooping(new_looping)) => {
+                is_looping = new_looping;
+            },
+
+            Ok(PlayerRequest::Shutdown) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+
+            Err(RecvTimeoutError::Timeout) => {
+                let now = position(anchor, anchor_position, speed);
+
+                while let Some(msg) = capture.messages.get(next) {
+                    if msg.timestamp > now {
+                        break;
+                    }
+
+                    let mut msg = msg.clone();
+                    msg.with_timestamp(origin.elapsed().as_micros() as u64);
+                    output.send(msg)?;
+                    next += 1;
+                }
+
+                if next < capture.messages.len() || now < length {
+                    continue;
+                }
+
+                // Loops are scheduled from the end of the previous one, so they don't drift.
+                // Empty captures would loop forever without sending anything.
+                if is_looping && length > 0 {
+                    anchor += Duration::from_micros(((length - anchor_position) as f64 / speed) as u64);
+                } else {
+                    is_playing = false;
+                    // Nobody might be waiting.
+                    finished.send(()).ok();
+                }
+
+                next = 0;
+                anchor_position = 0;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::MidiEvent;
+
+    #[test]
+    fn nan_speed() {
+        let mut msg = MidiEvent::Start.to_message("Test", 0);
+        msg.with_timestamp(1000);
+
+        let (send, recv) = unbounded();
+        let player = Player::new(Capture::new(vec![msg]), send);
+        player.set_speed(f64::NAN).unwrap();
+        player.play().unwrap();
+
+        assert!(recv.recv_timeout(TIMEOUT).is_ok());
+        player.wait().unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use std::error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, Sender, Receiver, RecvTimeoutError};
+
+use crate::Error;
+use crate::event::MidiEvent;
+use crate::message::MidiMessage;
+
+/// Clock messages per quarter note.
+pub const PPQN: u64 = 24;
+/// Clock messages per 16th note, the unit of song position.
+const CLOCKS_PER_SIXTEENTH: u64 = PPQN / 4;
+
+const MIN_BPM: f64 = 20.0;
+const MAX_BPM: f64 = 300.0;
+const DEFAULT_BPM: f64 = 120.0;
+
+/// Takes finite tempos only, NaN can't be clamped.
+fn tick_period(bpm: f64) -> Duration {
+    Duration::from_secs_f64(60.0 / (bpm.clamp(MIN_BPM, MAX_BPM) * PPQN as f64))
+}
+
+pub enum ClockRequest {
+    Start,
+    Stop,
+    Continue,
+    SetBpm(f64),
+    /// In 16th notes, only sent while stopped.
+    SongPosition(u16),
+
+    Shutdown
+}
+
+/// Sends 24 PPQN clock, and start, stop, continue and song position messages.
+///
+/// Clock keeps running while stopped, so followers can lock to the tempo beforehand.
+pub struct ClockMaster {
+    control_request: Sender<ClockRequest>
+}
+
+impl ClockMaster {
+    /// Runs at 120 BPM if `bpm` isn't finite.
+    pub fn new(name: &str, output: Sender<MidiMessage>, bpm: f64) -> ClockMaster {
+        let (exported_send, thread_recv) = unbounded();
+        let name = name.to_string();
+
+        thread::spawn(move || {
+            clock_wrapper(name, output, bpm, thread_recv);
+        });
+
+        ClockMaster{control_request: exported_send}
+    }
+
+    fn request(&self, request: ClockRequest) -> Result<(), Error> {
+        self.control_request.send(request)
+            .map_err(|_| Error::ChannelClosed("clock".to_string()))
+    }
+
+    /// Starts from the beginning of the song.
+    pub fn start(&self) -> Result<(), Error> {
+        self.request(ClockRequest::Start)
+    }
+
+    pub fn stop(&self) -> Result<(), Error> {
+        self.request(ClockRequest::Stop)
+    }
+
+    /// Starts from the current song position.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.request(ClockRequest::Continue)
+    }
+
+    /// Clamped to 20..=300 BPM. NaN and infinite tempos are ignored.
+    pub fn set_bpm(&self, bpm: f64) -> Result<(), Error> {
+        self.request(ClockRequest::SetBpm(bpm))
+    }
+
+    pub fn set_song_position(&self, sixteenths: u16) -> Result<(), Error> {
+        self.request(ClockRequest::SongPosition(sixteenths))
+    }
+}
+
+impl Drop for ClockMaster {
+    fn drop(&mut self) {
+        self.control_request.send(
+            ClockRequest::Shutdown ).ok();
+    }
+}
+
+
+fn clock_wrapper(name: String, output: Sender<MidiMessage>, bpm: f64, control_request: Receiver<ClockRequest>) {
+    match clock_thread(&name, &output, bpm, &control_request) {
+        Ok(()) => {},
+        Err(err) => {
+            panic!("clock died: {}", err);
+        }
+    };
+}
+
+fn clock_thread(name: &str, output: &Sender<MidiMessage>, bpm: f64, control_request: &Receiver<ClockRequest>) -> Result<(), Box<dyn error::Error>> {
+    let origin = Instant::now();
+    let mut period = tick_period(if bpm.is_finite() { bpm } else { DEFAULT_BPM });
+    let mut next_tick = origin;
+
+    let mut is_running = false;
+
+    let send = |event: MidiEvent| {
+        let mut msg = event.to_message(name, 0);
+        msg.with_timestamp(origin.elapsed().as_micros() as u64);
+        output.send(msg)
+    };
+
+    loop {
+        let timeout = next_tick.saturating_duration_since(Instant::now());
+
+        match control_request.recv_timeout(timeout) {
+            Ok(ClockRequest::Start) => {
+                is_running = true;
+                send(MidiEvent::Start)?;
+            },
+
+            Ok(ClockRequest::Stop) => {
+                is_running = false;
+                send(MidiEvent::Stop)?;
+            },
+
+            Ok(ClockRequest::Continue) => {
+                is_running = true;
+                send(MidiEvent::Continue)?;
+            },
+
+            Ok(ClockRequest::SetBpm(bpm)) => {
+                if bpm.is_finite() {
+                    period = tick_period(bpm);
+                }
+            },
+
+            Ok(ClockRequest::SongPosition(sixteenths)) => {
+                if !is_running {
+                    send(MidiEvent::SongPosition(sixteenths))?;
+                }
+            },
+
+            Ok(ClockRequest::Shutdown) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+
+            Err(RecvTimeoutError::Timeout) => {
+                send(MidiEvent::Clock)?;
+
+                // Ticks are scheduled from the previous one, so the tempo doesn't drift,
+                // unless we fell too far behind to catch up.
+                next_tick += period;
+                let now = Instant::now();
+                if next_tick + period < now {
+                    next_tick = now;
+                }
+            }
+        }
+    }
+}
+
+
+/// Something that happened to a followed clock.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockEvent {
+    /// A clock message while running, with the position in clocks.
+    Tick(u64),
+    /// Also a tick, on the first clock of a beat.
+    Beat(u64),
+    Start,
+    Stop,
+    Continue,
+    /// Song position changed, in clocks.
+    Position(u64)
+}
+
+/// Follows an incoming clock, fed messages one by one.
+///
+/// Uses the timestamps of the messages when they have one, otherwise the time they were fed.
+#[derive(Clone, Debug)]
+pub struct ClockFollower {
+    origin: Instant,
+    beats_per_bar: u64,
+
+    is_running: bool,
+    position: u64,
+
+    last_clock: Option<u64>,
+    /// Microseconds per clock, averaged.
+    period: Option<f64>
+}
+
+impl ClockFollower {
+    pub fn new() -> ClockFollower {
+        ClockFollower {
+            origin: Instant::now(),
+            beats_per_bar: 4,
+
+            is_running: false,
+            position: 0,
+
+            last_clock: None,
+            period: None
+        }
+    }
+
+    pub fn with_beats_per_bar(&mut self, beats_per_bar: u64) -> &mut ClockFollower {
+        self.beats_per_bar = beats_per_bar.max(1);
+        self
+    }
+
+    /// None for anything but clock related messages.
+    pub fn feed(&mut self, msg: &MidiMessage) -> Option<ClockEvent> {
+        let time = if msg.timestamp != 0 {
+            msg.timestamp
+        } else {
+            self.origin.elapsed().as_micros() as u64
+        };
+
+        match msg.event()? {
+            MidiEvent::Clock => {
+                if let Some(last) = self.last_clock {
+                    let period = time.saturating_sub(last) as f64;
+                    // Smoothed over roughly a beat.
+                    self.period = Some(match self.period {
+                        Some(average) => average + (period - average) / PPQN as f64,
+                        None => period
+                    });
+                }
+                self.last_clock = Some(time);
+
+                if !self.is_running {
+                    return None;
+                }
+
+                let position = self.position;
+                self.position += 1;
+
+                Some(match position % PPQN {
+                    0 => ClockEvent::Beat(position),
+                    _ => ClockEvent::Tick(position)
+                })
+            },
+
+            MidiEvent::Start => {
+                self.is_running = true;
+                self.position = 0;
+                Some(ClockEvent::Start)
+            },
+
+            MidiEvent::Stop => {
+                self.is_running = false;
+                Some(ClockEvent::Stop)
+            },
+
+            MidiEvent::Continue => {
+                self.is_running = true;
+                Some(ClockEvent::Continue)
+            },
+
+            MidiEvent::SongPosition(sixteenths) => {
+                self.position = sixteenths as u64 * CLOCKS_PER_SIXTEENTH;
+                Some(ClockEvent::Position(self.position))
+            },
+
+            _ => None
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// None until two clocks arrived.
+    pub fn bpm(&self) -> Option<f64> {
+        self.period
+            .filter(|period| *period > 0.0)
+            .map(|period| 60_000_000.0 / (period * PPQN as f64))
+    }
+
+    /// Clocks since the start of the song.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Beats since the start of the song.
+    pub fn beat(&self) -> u64 {
+        self.position / PPQN
+    }
+
+    /// Bars since the start of the song.
+    pub fn bar(&self) -> u64 {
+        self.beat() / self.beats_per_bar
+    }
+
+    /// Beat within the current bar.
+    pub fn beat_in_bar(&self) -> u64 {
+        self.beat() % self.beats_per_bar
+    }
+}
+
+impl Default for ClockFollower {
+    fn default() -> ClockFollower {
+        ClockFollower::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nan_tempo() {
+        let (send, recv) = unbounded();
+        let clock = ClockMaster::new("Clock", send, f64::NAN);
+        assert!(recv.recv_timeout(Duration::from_secs(1)).is_ok());
+
+        clock.set_bpm(f64::NAN).unwrap();
+        recv.try_iter().count();
+        assert!(recv.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+}
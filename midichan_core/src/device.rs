@@ -1,6 +1,7 @@
-use std::error::Error;
+use std::error;
 
-use crate::message::MidiMessage;
+use crate::Error;
+use crate::message::{MidiMessage, OutputPolicy, RouterStats};
 use crossbeam_channel::{Sender, Receiver};
 
 pub trait Controllable<R, T> {
@@ -17,27 +18,68 @@ pub trait HasOutput {
 }
 
 pub trait MidiDevice {
-    fn open_port(&self, name: String, port_id: usize) -> Result<(), String>;
-    fn close_port(&self, name: String) -> Result<(), String>;
+    fn open_port(&self, name: String, port_id: usize) -> Result<(), Error>;
+    fn close_port(&self, name: String) -> Result<(), Error>;
 
-    fn query(&self, name: String) -> Result<bool, String>;
-    fn query_all(&self) -> Result<Vec<String>, String>;
+    fn query(&self, name: String) -> Result<bool, Error>;
+    fn query_all(&self) -> Result<Vec<String>, Error>;
+}
+
+/// Decides which outputs a message goes to, and may modify it on the way.
+///
+/// Targets are output names, or patterns like "all" and "launchpad-*", see `router::targets`.
+///
+/// Implemented for every `FnMut(&mut MidiMessage) -> Vec<String>` closure,
+/// so routing logic can keep state between messages.
+pub trait RoutingFunction: Send {
+    fn route(&mut self, msg: &mut MidiMessage) -> Vec<String>;
+}
+
+impl<T: Send + FnMut(&mut MidiMessage) -> Vec<String>> RoutingFunction for T {
+    fn route(&mut self, msg: &mut MidiMessage) -> Vec<String> {
+        self(msg)
+    }
+}
+
+/// Rewrites messages before they reach a router output.
+///
+/// Returning false drops the message for that output.
+pub trait Transform: Send {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool;
+}
+
+impl<T: Send + FnMut(&mut MidiMessage) -> bool> Transform for T {
+    fn apply(&mut self, msg: &mut MidiMessage) -> bool {
+        self(msg)
+    }
 }
 
 pub trait RoutingDevice {
-    fn add_input(&self, name: String, port: Receiver<MidiMessage>) -> Result<(), String>;
-    fn add_output(&self, name: String, port: Sender<MidiMessage>) -> Result<(), String>;
+    fn add_input(&self, name: String, port: Receiver<MidiMessage>) -> Result<(), Error>;
+    fn add_output(&self, name: String, port: Sender<MidiMessage>) -> Result<(), Error>;
 
-    fn remove_input(&self, name: String) -> Result<(), String>;
-    fn remove_output(&self, name: String) -> Result<(), String>;
+    fn remove_input(&self, name: String) -> Result<(), Error>;
+    fn remove_output(&self, name: String) -> Result<(), Error>;
     
-    fn query_input(&self, name: String) -> Result<bool, String>;
-    fn query_output(&self, name: String) -> Result<bool, String>;
-    fn query_all_inputs(&self) -> Result<Vec<String>, String>;
-    fn query_all_outputs(&self) -> Result<Vec<String>, String>;
-    fn query_all(&self) -> Result<(Vec<String>, Vec<String>), String>;
+    fn query_input(&self, name: String) -> Result<bool, Error>;
+    fn query_output(&self, name: String) -> Result<bool, Error>;
+    fn query_all_inputs(&self) -> Result<Vec<String>, Error>;
+    fn query_all_outputs(&self) -> Result<Vec<String>, Error>;
+    fn query_all(&self) -> Result<(Vec<String>, Vec<String>), Error>;
+
+    fn stats(&self) -> Result<RouterStats, Error>;
+
+    /// Replaces the routing function, keeping inputs, outputs and queued messages.
+    fn set_function(&self, router_func: Box<dyn RoutingFunction>) -> Result<(), Error>;
+
+    /// Applies `transform` to everything sent to the output, replacing its previous transform.
+    fn set_transform(&self, output: String, transform: Box<dyn Transform>) -> Result<(), Error>;
+    fn clear_transform(&self, output: String) -> Result<(), Error>;
+
+    /// Sets what happens to messages for the output when its channel is full. Blocks by default.
+    fn set_policy(&self, output: String, policy: OutputPolicy) -> Result<(), Error>;
 }
 
 pub trait Application {
-    fn run(&mut self) -> Result<(), Box<dyn Error>>;
+    fn run(&mut self) -> Result<(), Box<dyn error::Error>>;
 }
\ No newline at end of file
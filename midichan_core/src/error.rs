@@ -0,0 +1,44 @@
+use std::error;
+use std::fmt;
+
+/// Errors returned by devices and routers.
+///
+/// Each variant carries the name of the component or port it concerns.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Error {
+    /// The handler thread did not answer in time.
+    Timeout(String),
+    /// Unexpected response, or stale responses still queued.
+    Desync(String),
+    /// The handler thread is gone.
+    ChannelClosed(String),
+    PortNotFound(String),
+    AlreadyOpen(String),
+    /// Request not handled by this device.
+    Unsupported(String),
+    /// Error reported by the MIDI backend.
+    Backend(String)
+}
+
+impl Error {
+    /// Timeouts and desyncs may go away on their own, the rest won't.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::Timeout(_) | Error::Desync(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Timeout(name) => write!(f, "{} timed out", name),
+            Error::Desync(name) => write!(f, "{} desync", name),
+            Error::ChannelClosed(name) => write!(f, "{}: channel closed", name),
+            Error::PortNotFound(name) => write!(f, "port not found: {}", name),
+            Error::AlreadyOpen(name) => write!(f, "device already added: {}", name),
+            Error::Unsupported(name) => write!(f, "unsupported request: {}", name),
+            Error::Backend(err) => write!(f, "backend error: {}", err)
+        }
+    }
+}
+
+impl error::Error for Error {}
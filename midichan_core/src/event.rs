@@ -0,0 +1,206 @@
+use crate::message::{MidiMessage, MessageType, ParseError};
+
+/// Typed view of a `MidiMessage`, without the device, channel and timestamp.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum MidiEvent {
+    NoteOff { note: u8, vel: u8 },
+    NoteOn { note: u8, vel: u8 },
+    PolyPressure { note: u8, pressure: u8 },
+    ControlChange { cc: u8, value: u8 },
+    ProgramChange(u8),
+    ChannelPressure(u8),
+    /// -8192..=8191, centered on 0.
+    PitchBend(i16),
+
+    /// The whole SysEx frame, including the 0xF0 and 0xF7 bytes.
+    /// Empty for messages without SysEx data.
+    SysEx(Vec<u8>),
+    /// Quarter frame message type and value, as sent on the wire.
+    Timecode(u8),
+    /// 14-bit song position, in 16th notes.
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset
+}
+
+impl MidiEvent {
+    /// None for messages of unknown type.
+    pub fn from_message(msg: &MidiMessage) -> Option<MidiEvent> {
+        let (key, velocity) = (msg.key & 0x7F, msg.velocity & 0x7F);
+
+        Some(match msg.msg_type {
+            MessageType::NoteOff => MidiEvent::NoteOff { note: key, vel: velocity },
+            MessageType::NoteOn => MidiEvent::NoteOn { note: key, vel: velocity },
+            MessageType::NoteVelocity => MidiEvent::PolyPressure { note: key, pressure: velocity },
+            MessageType::CC => MidiEvent::ControlChange { cc: key, value: velocity },
+            MessageType::PC => MidiEvent::ProgramChange(key),
+            MessageType::CCVelocity => MidiEvent::ChannelPressure(key),
+            MessageType::PitchBend => MidiEvent::PitchBend(((velocity as i16) << 7 | key as i16) - 0x2000),
+
+            MessageType::SysEx => MidiEvent::SysEx(msg.sysex.to_owned().unwrap_or_default()),
+            MessageType::Timecode => MidiEvent::Timecode(key),
+            MessageType::SongPosition => MidiEvent::SongPosition((velocity as u16) << 7 | key as u16),
+            MessageType::SongSelect => MidiEvent::SongSelect(key),
+            MessageType::TuneRequest => MidiEvent::TuneRequest,
+
+            MessageType::Clock => MidiEvent::Clock,
+            MessageType::Start => MidiEvent::Start,
+            MessageType::Continue => MidiEvent::Continue,
+            MessageType::Stop => MidiEvent::Stop,
+            MessageType::ActiveSensing => MidiEvent::ActiveSensing,
+            MessageType::Reset => MidiEvent::Reset,
+
+            MessageType::Unknown => return None
+        })
+    }
+
+    /// Parses exactly one complete message, returning its channel alongside the event.
+    pub fn from_raw(slice: &[u8]) -> Result<(u8, MidiEvent), ParseError> {
+        let msg = MidiMessage::from_raw("", 0, slice)?;
+
+        match MidiEvent::from_message(&msg) {
+            Some(event) => Ok((msg.channel, event)),
+            None => Err(ParseError::Undefined(slice[0]))
+        }
+    }
+
+    pub fn to_message(&self, name: &str, channel: u8) -> MidiMessage {
+        let (msg_type, key, velocity) = match *self {
+            MidiEvent::NoteOff { note, vel } => (MessageType::NoteOff, note, vel),
+            MidiEvent::NoteOn { note, vel } => (MessageType::NoteOn, note, vel),
+            MidiEvent::PolyPressure { note, pressure } => (MessageType::NoteVelocity, note, pressure),
+            MidiEvent::ControlChange { cc, value } => (MessageType::CC, cc, value),
+            MidiEvent::ProgramChange(program) => (MessageType::PC, program, 0),
+            MidiEvent::ChannelPressure(pressure) => (MessageType::CCVelocity, pressure, 0),
+            MidiEvent::PitchBend(bend) => {
+                let value = (bend.clamp(-0x2000, 0x1FFF) + 0x2000) as u16;
+                (MessageType::PitchBend, (value & 0x7F) as u8, (value >> 7) as u8)
+            },
+
+            MidiEvent::SysEx(_) => (MessageType::SysEx, 0, 0),
+            MidiEvent::Timecode(value) => (MessageType::Timecode, value, 0),
+            MidiEvent::SongPosition(pos) => (MessageType::SongPosition, (pos & 0x7F) as u8, ((pos >> 7) & 0x7F) as u8),
+            MidiEvent::SongSelect(song) => (MessageType::SongSelect, song, 0),
+            MidiEvent::TuneRequest => (MessageType::TuneRequest, 0, 0),
+
+            MidiEvent::Clock => (MessageType::Clock, 0, 0),
+            MidiEvent::Start => (MessageType::Start, 0, 0),
+            MidiEvent::Continue => (MessageType::Continue, 0, 0),
+            MidiEvent::Stop => (MessageType::Stop, 0, 0),
+            MidiEvent::ActiveSensing => (MessageType::ActiveSensing, 0, 0),
+            MidiEvent::Reset => (MessageType::Reset, 0, 0)
+        };
+
+        let channel = if msg_type.is_channel() { channel & 0x0F } else { 0 };
+
+        MidiMessage{
+            device: name.to_string(),
+            timestamp: 0,
+            channel,
+            msg_type,
+            key: key & 0x7F,
+            velocity: velocity & 0x7F,
+            sysex: match self {
+                MidiEvent::SysEx(data) if !data.is_empty() => Some(data.clone()),
+                _ => None
+            }
+        }
+    }
+
+    pub fn to_raw(&self, channel: u8) -> Vec<u8> {
+        self.to_message("", channel).to_raw()
+    }
+
+    pub fn is_channel(&self) -> bool {
+        matches!(self,
+            MidiEvent::NoteOff { .. } | MidiEvent::NoteOn { .. } | MidiEvent::PolyPressure { .. } |
+            MidiEvent::ControlChange { .. } | MidiEvent::ProgramChange(_) |
+            MidiEvent::ChannelPressure(_) | MidiEvent::PitchBend(_))
+    }
+}
+
+impl MidiMessage {
+    pub fn from_event(name: &str, channel: u8, event: &MidiEvent) -> MidiMessage {
+        event.to_message(name, channel)
+    }
+
+    pub fn event(&self) -> Option<MidiEvent> {
+        MidiEvent::from_message(self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<MidiEvent> {
+        vec![
+            MidiEvent::NoteOff { note: 60, vel: 64 },
+            MidiEvent::NoteOn { note: 127, vel: 1 },
+            MidiEvent::PolyPressure { note: 0, pressure: 127 },
+            MidiEvent::ControlChange { cc: 7, value: 100 },
+            MidiEvent::ProgramChange(42),
+            MidiEvent::ChannelPressure(90),
+            MidiEvent::PitchBend(-8192),
+            MidiEvent::PitchBend(0),
+            MidiEvent::PitchBend(8191),
+            MidiEvent::SysEx(vec![0xF0, 0x00, 0x20, 0x29, 0xF7]),
+            MidiEvent::Timecode(0x35),
+            MidiEvent::SongPosition(0x3FFF),
+            MidiEvent::SongSelect(3),
+            MidiEvent::TuneRequest,
+            MidiEvent::Clock,
+            MidiEvent::Start,
+            MidiEvent::Continue,
+            MidiEvent::Stop,
+            MidiEvent::ActiveSensing,
+            MidiEvent::Reset
+        ]
+    }
+
+    #[test]
+    fn message_round_trip() {
+        for event in events() {
+            let msg = event.to_message("Test", 5);
+            assert_eq!(msg.channel, if event.is_channel() { 5 } else { 0 });
+            assert_eq!(MidiEvent::from_message(&msg), Some(event.clone()));
+            assert_eq!(msg.event().map(|event| event.to_message("Test", 5)), Some(msg));
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        for event in events() {
+            let channel = if event.is_channel() { 9 } else { 0 };
+            assert_eq!(MidiEvent::from_raw(&event.to_raw(9)), Ok((channel, event)));
+        }
+    }
+
+    #[test]
+    fn pitch_bend_edges() {
+        for (raw, bend) in [([0xE0, 0x00, 0x00], -8192), ([0xE0, 0x00, 0x40], 0), ([0xE0, 0x7F, 0x7F], 8191)].iter() {
+            assert_eq!(MidiEvent::from_raw(raw), Ok((0, MidiEvent::PitchBend(*bend))));
+            assert_eq!(MidiEvent::PitchBend(*bend).to_raw(0), raw.to_vec());
+        }
+
+        assert_eq!(MidiEvent::PitchBend(i16::MAX).to_raw(0), vec![0xE0, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn sysex_without_data() {
+        let mut msg = MidiMessage::new("Test");
+        msg.with_msg_type(MessageType::SysEx);
+
+        let event = msg.event().unwrap();
+        assert_eq!(event, MidiEvent::SysEx(vec![]));
+        assert_eq!(event.to_message("Test", 0), msg);
+    }
+}
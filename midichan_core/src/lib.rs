@@ -1,2 +1,9 @@
 pub mod message;
-pub mod device;
\ No newline at end of file
+pub mod device;
+pub mod event;
+pub mod error;
+pub mod clock;
+pub mod smf;
+pub mod capture;
+
+pub use error::Error;
\ No newline at end of file
@@ -1,5 +1,11 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
 use crossbeam_channel::{Sender, Receiver};
 
+use crate::device::{RoutingFunction, Transform};
+
 macro_rules! num_to_enum {
     ($num:expr => $enm:ident{ $($fld:ident),+ }; $err:expr) => ({
         match $num {
@@ -11,7 +17,6 @@ macro_rules! num_to_enum {
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum MessageType {
-    SysEx = 0x70,
     NoteOff = 0x80,
     NoteOn = 0x90,
     NoteVelocity = 0xA0,
@@ -19,27 +24,102 @@ pub enum MessageType {
     PC = 0xC0,
     CCVelocity = 0xD0,
     PitchBend = 0xE0,
-    Unknown = 0xFE
+
+    SysEx = 0xF0,
+    Timecode = 0xF1,
+    SongPosition = 0xF2,
+    SongSelect = 0xF3,
+    TuneRequest = 0xF6,
+
+    Clock = 0xF8,
+    Start = 0xFA,
+    Continue = 0xFB,
+    Stop = 0xFC,
+    ActiveSensing = 0xFE,
+    Reset = 0xFF,
+
+    /// Undefined status byte, never sent out.
+    Unknown = 0xF4
 }
 
 impl MessageType {
+    /// Takes the high nibble of a channel status byte.
     pub fn from_u8(num: u8) -> MessageType {
         num_to_enum!(
-            num => MessageType{SysEx, NoteOff, NoteOn, NoteVelocity, CC, PC, CCVelocity, PitchBend};
+            num => MessageType{NoteOff, NoteOn, NoteVelocity, CC, PC, CCVelocity, PitchBend};
             MessageType::Unknown
         )
     }
+
+    /// Takes a full status byte, channel or system.
+    pub fn from_status(status: u8) -> MessageType {
+        if status < 0xF0 {
+            MessageType::from_u8(status & 0xF0)
+        } else {
+            num_to_enum!(
+                status => MessageType{SysEx, Timecode, SongPosition, SongSelect, TuneRequest,
+                    Clock, Start, Continue, Stop, ActiveSensing, Reset};
+                MessageType::Unknown
+            )
+        }
+    }
+
+    /// Amount of data bytes following the status byte, None for SysEx and undefined types.
+    pub fn data_len(&self) -> Option<usize> {
+        match self {
+            MessageType::NoteOff | MessageType::NoteOn | MessageType::NoteVelocity |
+            MessageType::CC | MessageType::PitchBend | MessageType::SongPosition => Some(2),
+
+            MessageType::PC | MessageType::CCVelocity |
+            MessageType::Timecode | MessageType::SongSelect => Some(1),
+
+            MessageType::TuneRequest | MessageType::Clock | MessageType::Start |
+            MessageType::Continue | MessageType::Stop | MessageType::ActiveSensing |
+            MessageType::Reset => Some(0),
+
+            MessageType::SysEx | MessageType::Unknown => None
+        }
+    }
+
+    pub fn is_channel(&self) -> bool {
+        (self.clone() as u8) < 0xF0
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        (self.clone() as u8) >= 0xF8
+    }
 }
 
-// #[derive(Clone)]
-// pub enum SystemMessageType {
-    // SysEx = 0xF0,
-    // Timecode = 0xF1,
-    // SongPosition = 0xF2,
-    // SongSelect = 0xF3,
-    // TuneRequest = 0xF6,
-    // EndOfSysEx = 0xF7
-// }
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ParseError {
+    /// No bytes were given.
+    Empty,
+    /// The message with this status byte ended before all of its data arrived.
+    Truncated(u8),
+    /// Data byte without a status byte or running status in effect.
+    UnexpectedData(u8),
+    /// 0xF7 outside of a SysEx message.
+    UnexpectedEndOfSysEx,
+    /// Status bytes 0xF4, 0xF5, 0xF9 and 0xFD.
+    Undefined(u8),
+    /// A complete message was followed by this many extra bytes.
+    TrailingData(usize)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty midi message"),
+            ParseError::Truncated(status) => write!(f, "truncated midi message (status {:#04X})", status),
+            ParseError::UnexpectedData(data) => write!(f, "data byte {:#04X} without status", data),
+            ParseError::UnexpectedEndOfSysEx => write!(f, "end of sysex outside of sysex message"),
+            ParseError::Undefined(status) => write!(f, "undefined status byte {:#04X}", status),
+            ParseError::TrailingData(len) => write!(f, "{} bytes after complete midi message", len)
+        }
+    }
+}
+
+impl Error for ParseError {}
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct MidiMessage {
@@ -47,43 +127,38 @@ pub struct MidiMessage {
     pub timestamp: u64,
     pub channel: u8,
     pub msg_type: MessageType,
+    /// First data byte
     pub key: u8,
+    /// Second data byte
     pub velocity: u8,
+    /// The whole SysEx frame, including the 0xF0 and 0xF7 bytes.
     pub sysex: Option<Vec<u8>>
 }
 
 impl MidiMessage {
-    pub fn from_raw(name: &str, timestamp: u64, slice: &[u8]) -> MidiMessage {
-        //println!("Msg get!: {:?}", slice);
-        let msg_type = slice[0] & 0xF0;
-
-        if matches!(msg_type, 0x70 | 0xD0) {
-            let mut vec= Vec::new();
-            vec.extend_from_slice(&slice[1..slice.len() - 1]);
-
-            MidiMessage{
-                device: name.to_string(),
-                timestamp,
-                channel: 0,
-                msg_type: MessageType::from_u8(msg_type),
-                key: 0,
-                velocity: 0,
-                sysex: Some(vec)
+    /// Parses exactly one complete message.
+    pub fn from_raw(name: &str, timestamp: u64, slice: &[u8]) -> Result<MidiMessage, ParseError> {
+        if slice.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let mut parser = MidiParser::new(name);
+
+        for (index, byte) in slice.iter().enumerate() {
+            let (truncated, result) = parser.step(timestamp, *byte);
+            if let Some(err) = truncated {
+                return Err(err);
             }
-        } else {
-            let channel = slice[0] & 0x0F;
-            let key = slice[1];
-            let velocity = slice[2];
-            MidiMessage{
-                device: name.to_string(),
-                timestamp,
-                channel,
-                msg_type: MessageType::from_u8(msg_type),
-                key,
-                velocity,
-                sysex: None
+
+            if let Some(msg) = result? {
+                return match slice.len() - index - 1 {
+                    0 => Ok(msg),
+                    rest => Err(ParseError::TrailingData(rest))
+                };
             }
         }
+
+        Err(ParseError::Truncated(parser.pending_status().unwrap_or(slice[0])))
     }
 
     pub fn new(name: &str) -> MidiMessage {
@@ -124,11 +199,196 @@ impl MidiMessage {
     }
 
     pub fn to_raw(&self) -> Vec<u8> {
-        match self.msg_type {
-            MessageType::SysEx => self.sysex.to_owned().unwrap_or_default(),
-            _ => vec![(self.msg_type.clone() as u8) | self.channel, self.key, self.velocity]
+        let status = if self.msg_type.is_channel() {
+            (self.msg_type.clone() as u8) | (self.channel & 0x0F)
+        } else {
+            self.msg_type.clone() as u8
+        };
+
+        match self.msg_type.data_len() {
+            Some(0) => vec![status],
+            Some(1) => vec![status, self.key & 0x7F],
+            Some(_) => vec![status, self.key & 0x7F, self.velocity & 0x7F],
+            None if self.msg_type == MessageType::SysEx => self.sysex.to_owned().unwrap_or_default(),
+            None => Vec::new()
+        }
+    }
+}
+
+/// Streaming parser for the MIDI 1.0 wire format.
+///
+/// Keeps running status and partial messages between calls,
+/// so input can be fed in arbitrary chunks.
+#[derive(Clone, Debug)]
+pub struct MidiParser {
+    name: String,
+    running_status: Option<u8>,
+    pending: Vec<u8>,
+    in_sysex: bool
+}
+
+impl MidiParser {
+    pub fn new(name: &str) -> MidiParser {
+        MidiParser{
+            name: name.to_string(),
+            running_status: None,
+            pending: Vec::new(),
+            in_sysex: false
+        }
+    }
+
+    /// Status byte of the message currently being assembled.
+    pub fn pending_status(&self) -> Option<u8> {
+        self.pending.first().copied()
+    }
+
+    pub fn reset(&mut self) {
+        self.running_status = None;
+        self.pending.clear();
+        self.in_sysex = false;
+    }
+
+    /// Parses a chunk of bytes, with the errors met along the way.
+    ///
+    /// Offending messages are dropped, and parsing goes on with the next byte.
+    pub fn parse(&mut self, timestamp: u64, bytes: &[u8]) -> (Vec<MidiMessage>, Vec<ParseError>) {
+        let mut messages = Vec::new();
+        let mut errors = Vec::new();
+
+        for byte in bytes {
+            let (truncated, result) = self.step(timestamp, *byte);
+            errors.extend(truncated);
+
+            match result {
+                Ok(Some(msg)) => messages.push(msg),
+                Ok(None) => (),
+                Err(err) => errors.push(err)
+            }
+        }
+
+        (messages, errors)
+    }
+
+    /// Feeds a single byte, returning a message once one is complete.
+    ///
+    /// A tune request cutting off a partial message is returned instead of the
+    /// `Truncated` error, `parse` reports both.
+    pub fn feed(&mut self, timestamp: u64, byte: u8) -> Result<Option<MidiMessage>, ParseError> {
+        match self.step(timestamp, byte) {
+            (_, Ok(Some(msg))) => Ok(Some(msg)),
+            (Some(err), _) => Err(err),
+            (None, result) => result
+        }
+    }
+
+    /// The message cut off by the byte, if any, and the result of the byte itself.
+    fn step(&mut self, timestamp: u64, byte: u8) -> (Option<ParseError>, Result<Option<MidiMessage>, ParseError>) {
+        // Realtime bytes don't interrupt the message being assembled, other status bytes do.
+        let truncated = match byte {
+            0x80..=0xF6 => self.pending_status().map(ParseError::Truncated),
+            _ => None
+        };
+
+        (truncated, self.feed_byte(timestamp, byte))
+    }
+
+    fn feed_byte(&mut self, timestamp: u64, byte: u8) -> Result<Option<MidiMessage>, ParseError> {
+        match byte {
+            // Realtime messages may appear anywhere, even inside SysEx.
+            0xF8..=0xFF => {
+                let msg_type = MessageType::from_status(byte);
+                if msg_type == MessageType::Unknown {
+                    return Err(ParseError::Undefined(byte));
+                }
+
+                Ok(Some(self.message(timestamp, msg_type, 0, 0, 0)))
+            },
+
+            0xF7 => {
+                if !self.in_sysex {
+                    return Err(ParseError::UnexpectedEndOfSysEx);
+                }
+
+                self.pending.push(byte);
+                self.in_sysex = false;
+
+                let mut msg = self.message(timestamp, MessageType::SysEx, 0, 0, 0);
+                msg.sysex = Some(self.pending.split_off(0));
+                Ok(Some(msg))
+            },
+
+            0x80..=0xF6 => {
+                self.pending.clear();
+                self.in_sysex = false;
+
+                let msg_type = MessageType::from_status(byte);
+                match msg_type {
+                    MessageType::Unknown => {
+                        self.running_status = None;
+                        Err(ParseError::Undefined(byte))
+                    },
+
+                    MessageType::TuneRequest => {
+                        self.running_status = None;
+                        Ok(Some(self.message(timestamp, msg_type, 0, 0, 0)))
+                    },
+
+                    MessageType::SysEx => {
+                        self.running_status = None;
+                        self.in_sysex = true;
+                        self.pending.push(byte);
+                        Ok(None)
+                    },
+
+                    _ => {
+                        self.running_status = if msg_type.is_channel() { Some(byte) } else { None };
+                        self.pending.push(byte);
+                        Ok(None)
+                    }
+                }
+            },
+
+            _ => {
+                if self.in_sysex {
+                    self.pending.push(byte);
+                    return Ok(None);
+                }
+
+                if self.pending.is_empty() {
+                    match self.running_status {
+                        Some(status) => self.pending.push(status),
+                        None => return Err(ParseError::UnexpectedData(byte))
+                    }
+                }
+                self.pending.push(byte);
+
+                let status = self.pending[0];
+                let msg_type = MessageType::from_status(status);
+
+                if Some(self.pending.len() - 1) == msg_type.data_len() {
+                    let key = self.pending.get(1).copied().unwrap_or(0);
+                    let velocity = self.pending.get(2).copied().unwrap_or(0);
+                    let channel = if msg_type.is_channel() { status & 0x0F } else { 0 };
+                    self.pending.clear();
+
+                    Ok(Some(self.message(timestamp, msg_type, channel, key, velocity)))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    fn message(&self, timestamp: u64, msg_type: MessageType, channel: u8, key: u8, velocity: u8) -> MidiMessage {
+        MidiMessage{
+            device: self.name.clone(),
+            timestamp,
+            channel,
+            msg_type,
+            key,
+            velocity,
+            sysex: None
         }
-        
     }
 }
 
@@ -136,14 +396,36 @@ impl MidiMessage {
 pub enum DeviceResponse {
     Device(String, bool),
     List(Vec<String>),
-    Error(String),
+    Error(crate::Error),
     Ok
 }
 
+/// Sent by physical devices when ports come and go.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    /// A port showed up in the port list.
+    PortAdded(String),
+    /// A port disappeared from the port list.
+    PortRemoved(String),
+
+    /// An opened device lost its port.
+    Disconnected(String),
+    /// An opened device was reopened after its port came back.
+    Reconnected(String),
+    /// A message from an opened device was dropped, nobody read the input in time.
+    Dropped(String),
+
+    /// The port list couldn't be read, it is tried again on the next rescan.
+    ScanFailed(crate::Error)
+}
+
 #[derive(Clone)]
 pub enum DeviceRequest {
     OpenIn(String, usize),
     OpenOut(String, usize),
+    /// Creates a port other software can connect to, where supported.
+    OpenVirtualIn(String),
+    OpenVirtualOut(String),
 
     QueryDevice(String),
     QueryList,
@@ -154,17 +436,55 @@ pub enum DeviceRequest {
     Shutdown
 }
 
+/// What a router output does when its channel is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OutputPolicy {
+    /// Waits for room, stalling the whole router.
+    #[default]
+    Block,
+    DropNewest,
+    /// Holds messages back in the router, dropping the oldest ones once too many pile up.
+    DropOldest,
+    /// Like `DropOldest`, but a held back note or CC is replaced by newer ones for the same LED.
+    CoalesceLeds
+}
+
+/// Traffic through a single router input or output.
+#[derive(Clone, Debug, Default)]
+pub struct RouteStats {
+    pub messages: u64,
+    /// Inputs: messages that reached no output.
+    /// Outputs: failed sends, and messages dropped or replaced by the policy.
+    pub drops: u64,
+    /// Outputs: messages currently held back by the policy.
+    pub queued: usize,
+    pub last_seen: Option<Instant>
+}
+
+impl RouteStats {
+    pub fn record(&mut self) {
+        self.messages += 1;
+        self.last_seen = Some(Instant::now());
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RouterStats {
+    pub inputs: Vec<(String, RouteStats)>,
+    pub outputs: Vec<(String, RouteStats)>
+}
+
 #[derive(Clone)]
 pub enum RouterResponse {
     Device(String, bool),
 
     List(Vec<String>),
+    Stats(RouterStats),
 
-    Error(String),
+    Error(crate::Error),
     Ok
 }
 
-#[derive(Clone)]
 pub enum RouterRequest {
     AddInput(String, Receiver<MidiMessage>),
     RemoveInput(String),
@@ -175,6 +495,95 @@ pub enum RouterRequest {
     QueryOutput(String),
     QueryAllInputs,
     QueryAllOutputs,
+    Stats,
+
+    SetFunction(Box<dyn RoutingFunction>),
+    SetTransform(String, Box<dyn Transform>),
+    ClearTransform(String),
+    SetPolicy(String, OutputPolicy),
 
     Shutdown
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keeps_going_after_errors() {
+        let (messages, errors) = MidiParser::new("Test").parse(0, &[0x40, 0x90, 60, 100, 0xF4, 0x80, 60, 0]);
+
+        assert_eq!(errors, vec![ParseError::UnexpectedData(0x40), ParseError::Undefined(0xF4)]);
+        assert_eq!(messages.iter().map(|msg| msg.msg_type.clone()).collect::<Vec<_>>(), vec![MessageType::NoteOn, MessageType::NoteOff]);
+    }
+
+    fn summary(messages: &[MidiMessage]) -> Vec<(MessageType, u8, u8, u8)> {
+        messages.iter().map(|msg| (msg.msg_type.clone(), msg.channel, msg.key, msg.velocity)).collect()
+    }
+
+    #[test]
+    fn running_status() {
+        let (messages, errors) = MidiParser::new("Test").parse(0, &[0x91, 60, 100, 62, 100, 0xB1, 7, 64, 10, 20]);
+
+        assert!(errors.is_empty());
+        assert_eq!(summary(&messages), vec![
+            (MessageType::NoteOn, 1, 60, 100),
+            (MessageType::NoteOn, 1, 62, 100),
+            (MessageType::CC, 1, 7, 64),
+            (MessageType::CC, 1, 10, 20)
+        ]);
+
+        // System common messages cancel it.
+        let (messages, errors) = MidiParser::new("Test").parse(0, &[0x90, 60, 100, 0xF3, 5, 60]);
+
+        assert_eq!(errors, vec![ParseError::UnexpectedData(60)]);
+        assert_eq!(summary(&messages), vec![(MessageType::NoteOn, 0, 60, 100), (MessageType::SongSelect, 0, 5, 0)]);
+    }
+
+    #[test]
+    fn realtime_inside_messages() {
+        let (messages, errors) = MidiParser::new("Test").parse(0, &[0x90, 60, 0xF8, 100, 0xF0, 0x00, 0xFA, 0x20, 0xF7]);
+
+        assert!(errors.is_empty());
+        assert_eq!(summary(&messages), vec![
+            (MessageType::Clock, 0, 0, 0),
+            (MessageType::NoteOn, 0, 60, 100),
+            (MessageType::Start, 0, 0, 0),
+            (MessageType::SysEx, 0, 0, 0)
+        ]);
+        assert_eq!(messages[3].sysex, Some(vec![0xF0, 0x00, 0x20, 0xF7]));
+    }
+
+    #[test]
+    fn truncation() {
+        let mut parser = MidiParser::new("Test");
+
+        // Partial messages carry over to the next chunk.
+        assert_eq!(parser.parse(0, &[0x90, 60]), (vec![], vec![]));
+        assert_eq!(parser.pending_status(), Some(0x90));
+        let (messages, _) = parser.parse(0, &[100]);
+        assert_eq!(summary(&messages), vec![(MessageType::NoteOn, 0, 60, 100)]);
+
+        let (messages, errors) = parser.parse(0, &[0xE0, 0x7F, 0x80, 60, 0]);
+        assert_eq!(errors, vec![ParseError::Truncated(0xE0)]);
+        assert_eq!(summary(&messages), vec![(MessageType::NoteOff, 0, 60, 0)]);
+
+        assert_eq!(MidiMessage::from_raw("Test", 0, &[0x90, 60]), Err(ParseError::Truncated(0x90)));
+        assert_eq!(MidiMessage::from_raw("Test", 0, &[0xF0, 0x01]), Err(ParseError::Truncated(0xF0)));
+        assert_eq!(MidiMessage::from_raw("Test", 0, &[0xC0, 1, 2]), Err(ParseError::TrailingData(1)));
+    }
+
+    #[test]
+    fn tune_request_after_partial_message() {
+        for (bytes, status) in [(&[0x90, 60, 0xF6][..], 0x90), (&[0xF0, 0x01, 0xF6][..], 0xF0)].iter() {
+            let (messages, errors) = MidiParser::new("Test").parse(0, bytes);
+
+            assert_eq!(errors, vec![ParseError::Truncated(*status)]);
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].msg_type, MessageType::TuneRequest);
+        }
+
+        assert_eq!(MidiMessage::from_raw("Test", 0, &[0x90, 0xF6]), Err(ParseError::Truncated(0x90)));
+    }
+}
@@ -0,0 +1,571 @@
+//! Standard MIDI Files, types 0 and 1.
+//!
+//! Message timestamps are in microseconds, like the ones midir gives,
+//! and get converted to and from ticks through the tempo map.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::message::{MidiMessage, MessageType, ParseError};
+
+/// 120 BPM, the tempo until the first tempo change.
+pub const DEFAULT_TEMPO: u32 = 500_000;
+/// Tempos are stored in 24 bits.
+const MAX_TEMPO: u32 = 0xFF_FFFF;
+
+#[derive(Debug)]
+pub enum SmfError {
+    Io(io::Error),
+    /// The file ended in the middle of a chunk or event.
+    UnexpectedEnd,
+    /// Expected a chunk with this id.
+    MissingChunk(&'static str),
+    /// Type 2 files and SMPTE time division.
+    Unsupported(String),
+    /// A tempo change to zero microseconds per quarter note, at this tick.
+    ZeroTempo(u64),
+    Message(ParseError)
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmfError::Io(err) => write!(f, "failed to access midi file: {}", err),
+            SmfError::UnexpectedEnd => write!(f, "unexpected end of midi file"),
+            SmfError::MissingChunk(id) => write!(f, "missing {} chunk in midi file", id),
+            SmfError::Unsupported(desc) => write!(f, "unsupported midi file: {}", desc),
+            SmfError::ZeroTempo(tick) => write!(f, "zero tempo at tick {} in midi file", tick),
+            SmfError::Message(err) => write!(f, "invalid event in midi file: {}", err)
+        }
+    }
+}
+
+impl Error for SmfError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u16)]
+pub enum SmfFormat {
+    /// Everything in one track.
+    SingleTrack = 0,
+    /// Simultaneous tracks, the tempo map is in the first one.
+    MultiTrack = 1
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tempo {
+    pub tick: u64,
+    pub micros_per_quarter: u32
+}
+
+impl Tempo {
+    /// Falls back to 120 BPM for NaN and tempos that aren't positive.
+    pub fn from_bpm(tick: u64, bpm: f64) -> Tempo {
+        let micros_per_quarter = match 60_000_000.0 / bpm {
+            micros if micros > 0.0 => micros.round().clamp(1.0, MAX_TEMPO as f64) as u32,
+            _ => DEFAULT_TEMPO
+        };
+
+        Tempo{tick, micros_per_quarter}
+    }
+
+    pub fn bpm(&self) -> f64 {
+        60_000_000.0 / self.micros_per_quarter as f64
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SmfTrack {
+    /// Read from and written as the track name meta event. Device name of the messages read.
+    pub name: String,
+    /// Sorted by timestamp.
+    pub messages: Vec<MidiMessage>
+}
+
+impl SmfTrack {
+    pub fn new(name: &str, messages: Vec<MidiMessage>) -> SmfTrack {
+        SmfTrack{name: name.to_string(), messages}
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Smf {
+    pub format: SmfFormat,
+    pub ticks_per_quarter: u16,
+    /// Sorted by tick.
+    pub tempos: Vec<Tempo>,
+    pub tracks: Vec<SmfTrack>
+}
+
+impl Smf {
+    pub fn new(format: SmfFormat) -> Smf {
+        Smf {
+            format,
+            ticks_per_quarter: 480,
+            tempos: Vec::new(),
+            tracks: Vec::new()
+        }
+    }
+
+    pub fn with_ticks_per_quarter(&mut self, ticks_per_quarter: u16) -> &mut Smf {
+        self.ticks_per_quarter = ticks_per_quarter.clamp(1, 0x7FFF);
+        self
+    }
+
+    pub fn with_tempo(&mut self, mut tempo: Tempo) -> &mut Smf {
+        tempo.micros_per_quarter = tempo.micros_per_quarter.clamp(1, MAX_TEMPO);
+        let index = self.tempos.iter().position(|x| x.tick > tempo.tick).unwrap_or(self.tempos.len());
+        self.tempos.insert(index, tempo);
+        self
+    }
+
+    pub fn with_track(&mut self, track: SmfTrack) -> &mut Smf {
+        self.tracks.push(track);
+        self
+    }
+
+    /// Tempo segments as (start tick, start time, micros per quarter).
+    fn segments(&self) -> Vec<(u64, u64, u32)> {
+        let mut segments = vec![(0, 0, DEFAULT_TEMPO)];
+
+        for tempo in self.tempos.iter() {
+            let (tick, micros, micros_per_quarter) = *segments.last().unwrap();
+            let start = micros + (tempo.tick - tick) * micros_per_quarter as u64 / self.ticks_per_quarter as u64;
+
+            if tempo.tick == tick {
+                segments.pop();
+            }
+            segments.push((tempo.tick, start, tempo.micros_per_quarter));
+        }
+
+        segments
+    }
+
+    pub fn micros_at(&self, tick: u64) -> u64 {
+        let segments = self.segments();
+        let (start_tick, start, micros_per_quarter) = *segments.iter().rev()
+            .find(|(start_tick, _, _)| *start_tick <= tick)
+            .unwrap();
+
+        start + (tick - start_tick) * micros_per_quarter as u64 / self.ticks_per_quarter as u64
+    }
+
+    pub fn tick_at(&self, micros: u64) -> u64 {
+        let segments = self.segments();
+        let (start_tick, start, micros_per_quarter) = *segments.iter().rev()
+            .find(|(_, start, _)| *start <= micros)
+            .unwrap();
+
+        let ticks = (micros - start) * self.ticks_per_quarter as u64;
+        start_tick + (ticks + micros_per_quarter as u64 / 2) / micros_per_quarter as u64
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Smf, SmfError> {
+        Smf::parse(&fs::read(path).map_err(SmfError::Io)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SmfError> {
+        fs::write(path, self.to_bytes()).map_err(SmfError::Io)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Smf, SmfError> {
+        let mut reader = Reader{data, pos: 0};
+
+        let header = reader.chunk("MThd")?;
+        let mut header = Reader{data: header, pos: 0};
+        let format = match header.u16()? {
+            0 => SmfFormat::SingleTrack,
+            1 => SmfFormat::MultiTrack,
+            format => return Err(SmfError::Unsupported(format!("type {}", format)))
+        };
+        let track_count = header.u16()?;
+        let division = header.u16()?;
+        if division & 0x8000 != 0 {
+            return Err(SmfError::Unsupported("SMPTE time division".to_string()));
+        }
+
+        let mut smf = Smf::new(format);
+        smf.ticks_per_quarter = division.max(1);
+
+        let mut tracks = Vec::new();
+        for _ in 0..track_count {
+            let track = reader.chunk("MTrk")?;
+            tracks.push(read_track(track, &mut smf.tempos)?);
+        }
+
+        smf.tempos.sort_by_key(|tempo| tempo.tick);
+
+        for (name, events) in tracks {
+            let messages = events.into_iter()
+                .map(|(tick, mut msg)| {
+                    msg.timestamp = smf.micros_at(tick);
+                    msg.device = name.clone();
+                    msg
+                })
+                .collect();
+
+            smf.tracks.push(SmfTrack{name, messages});
+        }
+
+        Ok(smf)
+    }
+
+    /// Type 0 files get all tracks merged into one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tracks: Vec<SmfTrack> = match self.format {
+            SmfFormat::SingleTrack => {
+                let mut messages: Vec<MidiMessage> = self.tracks.iter()
+                    .flat_map(|track| track.messages.iter().cloned())
+                    .collect();
+                messages.sort_by_key(|msg| msg.timestamp);
+
+                let name = self.tracks.first().map(|track| track.name.as_str()).unwrap_or("");
+                vec![SmfTrack::new(name, messages)]
+            },
+            SmfFormat::MultiTrack if self.tracks.is_empty() => vec![SmfTrack::default()],
+            SmfFormat::MultiTrack => self.tracks.clone()
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&(self.format as u16).to_be_bytes());
+        out.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.ticks_per_quarter.to_be_bytes());
+
+        for (index, track) in tracks.iter().enumerate() {
+            let mut events: Vec<(u64, Vec<u8>)> = Vec::new();
+
+            if !track.name.is_empty() {
+                let mut name = vec![0xFF, 0x03];
+                write_varlen(&mut name, track.name.len() as u64);
+                name.extend_from_slice(track.name.as_bytes());
+                events.push((0, name));
+            }
+
+            // The tempo map goes in the first track.
+            if index == 0 {
+                for tempo in self.tempos.iter() {
+                    let mut event = vec![0xFF, 0x51, 0x03];
+                    event.extend_from_slice(&tempo.micros_per_quarter.to_be_bytes()[1..]);
+                    events.push((tempo.tick, event));
+                }
+            }
+
+            for msg in track.messages.iter() {
+                if let Some(event) = encode_message(msg) {
+                    events.push((self.tick_at(msg.timestamp), event));
+                }
+            }
+
+            // Stable, so meta events stay before messages on the same tick.
+            events.sort_by_key(|(tick, _)| *tick);
+
+            let mut data = Vec::new();
+            let mut last_tick = 0;
+            for (tick, event) in events {
+                write_varlen(&mut data, tick - last_tick);
+                data.extend_from_slice(&event);
+                last_tick = tick;
+            }
+            data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+            out.extend_from_slice(b"MTrk");
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&data);
+        }
+
+        out
+    }
+}
+
+/// The event after the delta time, None for messages without a wire format.
+fn encode_message(msg: &MidiMessage) -> Option<Vec<u8>> {
+    let raw = msg.to_raw();
+
+    match msg.msg_type {
+        _ if raw.is_empty() => None,
+        MessageType::SysEx => {
+            let mut event = vec![0xF0];
+            write_varlen(&mut event, raw.len() as u64 - 1);
+            event.extend_from_slice(&raw[1..]);
+            Some(event)
+        },
+        ref msg_type if msg_type.is_channel() => Some(raw),
+        // System common and realtime messages can only be stored escaped.
+        _ => {
+            let mut event = vec![0xF7];
+            write_varlen(&mut event, raw.len() as u64);
+            event.extend_from_slice(&raw);
+            Some(event)
+        }
+    }
+}
+
+fn write_varlen(out: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+
+    out.extend(bytes.iter().rev());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SmfError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(SmfError::UnexpectedEnd)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, SmfError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SmfError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SmfError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn varlen(&mut self) -> Result<u64, SmfError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    /// The data of the next chunk with the id, skipping unknown chunks.
+    fn chunk(&mut self, id: &'static str) -> Result<&'a [u8], SmfError> {
+        loop {
+            if self.is_empty() {
+                return Err(SmfError::MissingChunk(id));
+            }
+
+            let chunk_id = self.bytes(4)?;
+            let len = self.u32()? as usize;
+            let data = self.bytes(len)?;
+
+            if chunk_id == id.as_bytes() {
+                return Ok(data);
+            }
+        }
+    }
+}
+
+/// Track name, and messages by tick.
+fn read_track(data: &[u8], tempos: &mut Vec<Tempo>) -> Result<(String, Vec<(u64, MidiMessage)>), SmfError> {
+    let mut reader = Reader{data, pos: 0};
+    let mut name = String::new();
+    let mut events = Vec::new();
+
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while !reader.is_empty() {
+        tick += reader.varlen()?;
+
+        match reader.byte()? {
+            0xFF => {
+                running_status = None;
+                let kind = reader.byte()?;
+                let len = reader.varlen()? as usize;
+                let meta = reader.bytes(len)?;
+
+                match (kind, meta) {
+                    (0x51, [0, 0, 0]) => return Err(SmfError::ZeroTempo(tick)),
+                    (0x51, [a, b, c]) => tempos.push(Tempo {
+                        tick,
+                        micros_per_quarter: u32::from_be_bytes([0, *a, *b, *c])
+                    }),
+                    (0x03, _) => name = String::from_utf8_lossy(meta).into_owned(),
+                    (0x2F, _) => break,
+                    _ => ()
+                }
+            },
+
+            0xF0 => {
+                running_status = None;
+                let len = reader.varlen()? as usize;
+
+                let mut sysex = vec![0xF0];
+                sysex.extend_from_slice(reader.bytes(len)?);
+                if sysex.last() != Some(&0xF7) {
+                    sysex.push(0xF7);
+                }
+
+                let mut msg = MidiMessage::new("");
+                msg.with_msg_type(MessageType::SysEx);
+                msg.sysex = Some(sysex);
+                events.push((tick, msg));
+            },
+
+            // Escaped bytes, kept when they hold a whole message.
+            0xF7 => {
+                running_status = None;
+                let len = reader.varlen()? as usize;
+
+                if let Ok(msg) = MidiMessage::from_raw("", 0, reader.bytes(len)?) {
+                    events.push((tick, msg));
+                }
+            },
+
+            byte => {
+                // Only channel messages set the running status, system messages clear it.
+                let (status, first) = match byte {
+                    0x80..=0xEF => {
+                        running_status = Some(byte);
+                        (byte, None)
+                    },
+                    0xF0..=0xFF => {
+                        running_status = None;
+                        (byte, None)
+                    },
+                    _ => {
+                        let status = running_status.ok_or(SmfError::Message(ParseError::UnexpectedData(byte)))?;
+                        (status, Some(byte))
+                    }
+                };
+
+                let len = MessageType::from_status(status).data_len()
+                    .ok_or(SmfError::Message(ParseError::Undefined(status)))?;
+
+                let mut raw = vec![status];
+                raw.extend(first);
+                let remaining = len.checked_sub(raw.len() - 1)
+                    .ok_or(SmfError::Message(ParseError::UnexpectedData(byte)))?;
+                raw.extend_from_slice(reader.bytes(remaining)?);
+
+                events.push((tick, MidiMessage::from_raw("", 0, &raw).map_err(SmfError::Message)?));
+            }
+        }
+    }
+
+    Ok((name, events))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(track: &[u8]) -> Vec<u8> {
+        let mut data = b"MThd".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        data.extend_from_slice(b"MTrk");
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(track);
+        data
+    }
+
+    fn message(device: &str, timestamp: u64, msg_type: MessageType, key: u8, velocity: u8) -> MidiMessage {
+        let mut msg = MidiMessage::new(device);
+        msg.with_timestamp(timestamp).with_msg_type(msg_type).with_key(key).with_velocity(velocity);
+        msg
+    }
+
+    fn contains(data: &[u8], bytes: &[u8]) -> bool {
+        data.windows(bytes.len()).any(|window| window == bytes)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut sysex = message("a", 500_000, MessageType::SysEx, 0, 0);
+        sysex.sysex = Some(vec![0xF0, 0x00, 0x20, 0x29, 0xF7]);
+
+        let first = vec![
+            message("a", 0, MessageType::NoteOn, 60, 100),
+            sysex,
+            message("a", 1_250_000, MessageType::NoteOff, 60, 0)
+        ];
+        let second = vec![
+            message("b", 1_000_000, MessageType::Clock, 0, 0),
+            message("b", 1_250_000, MessageType::SongPosition, 0x10, 0x01)
+        ];
+
+        let mut smf = Smf::new(SmfFormat::SingleTrack);
+        smf.with_ticks_per_quarter(500)
+            .with_tempo(Tempo{tick: 1000, micros_per_quarter: 250_000})
+            .with_track(SmfTrack::new("a", first.clone()))
+            .with_track(SmfTrack::new("b", second.clone()));
+
+        let data = smf.to_bytes();
+        assert!(contains(&data, &[0xF0, 0x04, 0x00, 0x20, 0x29, 0xF7]));
+        assert!(contains(&data, &[0xF7, 0x01, 0xF8]));
+        assert!(contains(&data, &[0xF7, 0x03, 0xF2, 0x10, 0x01]));
+
+        let parsed = Smf::parse(&data).unwrap();
+        assert_eq!(parsed.format, SmfFormat::SingleTrack);
+        assert_eq!(parsed.ticks_per_quarter, 500);
+        assert_eq!(parsed.tempos, vec![Tempo{tick: 1000, micros_per_quarter: 250_000}]);
+
+        // Type 0 merges everything into the first track, in time order.
+        let mut expected: Vec<MidiMessage> = first.into_iter().chain(second).collect();
+        expected.sort_by_key(|msg| msg.timestamp);
+        for msg in expected.iter_mut() {
+            msg.device = "a".to_string();
+        }
+
+        assert_eq!(parsed.tracks.len(), 1);
+        assert_eq!(parsed.tracks[0].name, "a");
+        assert_eq!(parsed.tracks[0].messages, expected);
+    }
+
+    #[test]
+    fn running_status() {
+        let smf = Smf::parse(&file(&[0x00, 0x90, 60, 100, 0x60, 60, 0, 0x00, 0xFF, 0x2F, 0x00])).unwrap();
+        let messages = &smf.tracks[0].messages;
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].msg_type, MessageType::NoteOn);
+        assert_eq!(messages[1].velocity, 0);
+        assert_eq!(messages[1].timestamp, 500_000);
+    }
+
+    #[test]
+    fn zero_tempo() {
+        let result = Smf::parse(&file(&[0x00, 0xFF, 0x51, 0x03, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x2F, 0x00]));
+        assert!(matches!(result, Err(SmfError::ZeroTempo(0))));
+
+        for bpm in [0.0, -120.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(Tempo::from_bpm(0, *bpm).micros_per_quarter > 0);
+        }
+        assert_eq!(Tempo::from_bpm(0, 0.0).micros_per_quarter, MAX_TEMPO);
+    }
+
+    #[test]
+    fn data_after_system_message() {
+        let result = Smf::parse(&file(&[0x00, 0xF6, 0x00, 0x40, 0x00, 0xFF, 0x2F, 0x00]));
+
+        assert!(matches!(result, Err(SmfError::Message(ParseError::UnexpectedData(0x40)))));
+    }
+
+    #[test]
+    fn data_after_meta_event() {
+        let result = Smf::parse(&file(&[0x00, 0x90, 60, 100, 0x00, 0xFF, 0x01, 0x00, 0x00, 60, 0, 0x00, 0xFF, 0x2F, 0x00]));
+
+        assert!(matches!(result, Err(SmfError::Message(ParseError::UnexpectedData(60)))));
+    }
+}